@@ -0,0 +1,129 @@
+//! Error types returned by this crate
+
+use std::fmt;
+use crate::printer::constants::WidthLength;
+use crate::printer::status::PrinterError;
+
+/// Everything that can go wrong while talking to a printer or preparing a label for it
+#[derive(Debug)]
+pub enum Error {
+	/// USB communication with the printer failed
+	Usb(rusb::Error),
+	/// The printer or USB device did not behave as the raster protocol describes
+	Protocol(&'static str),
+	/// The printer reported one or more errors in its status
+	Printer(PrinterError),
+	/// No label media is loaded in the printer
+	NoMedia,
+	/// The printer reported media dimensions that don't correspond to a known label type
+	UnknownMedia {
+		width: u8,
+		length: u8,
+	},
+	/// The loaded label media isn't the one the job requires. Dimensions are the tape size in mm.
+	MediaMismatch {
+		expected: WidthLength,
+		loaded: WidthLength,
+	},
+	/// The raster was rendered for a label whose width in dots differs from that of the loaded label media
+	LabelWidthMismatch {
+		rendered_for: u32,
+		loaded: u32,
+	},
+	/// A raster line's length in bytes doesn't match the printer's print head
+	RasterLineLength {
+		expected: usize,
+		found: usize,
+	},
+	/// A raster line prints on a pin outside of the loaded label media's printable area
+	RasterOutOfBounds {
+		pin: u32,
+	},
+	/// A page has more raster lines than fit on the loaded die-cut label
+	RasterTooLong {
+		lines: usize,
+		max: u32,
+	},
+	/// A custom label definition could not be parsed
+	LabelDefinition(String),
+	/// Reading a file failed
+	Io(std::io::Error),
+	/// An image could not be loaded
+	Image(image::ImageError),
+	/// Text or image content could not be converted into raster lines
+	Rasterization(String),
+	/// The connected printer model doesn't support the requested feature
+	Unsupported(&'static str),
+	/// An argument was outside of the range the printer accepts
+	InvalidArgument(&'static str),
+	/// The printer did not finish in the allotted time
+	Timeout,
+	/// The operation was cancelled by the caller
+	Cancelled,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Usb(err) => write!(f, "USB error: {}", err),
+			Error::Protocol(message) => write!(f, "Protocol error: {}", message),
+			Error::Printer(errors) => write!(f, "Printer error: {}", errors),
+			Error::NoMedia => write!(f, "No media loaded into printer"),
+			Error::UnknownMedia { width, length } => write!(f, "Unknown media loaded in printer ({}mm x {}mm)", width, length),
+			Error::MediaMismatch { expected, loaded } => write!(
+				f,
+				"Loaded media ({}mm x {}mm) does not match expected media ({}mm x {}mm)",
+				loaded.0, loaded.1, expected.0, expected.1
+			),
+			Error::LabelWidthMismatch { rendered_for, loaded } => write!(f, "Raster was rendered for a label {} dots wide but the loaded media is {} dots wide", rendered_for, loaded),
+			Error::RasterLineLength { expected, found } => write!(f, "Raster line is {} bytes long but the printer expects {} bytes", found, expected),
+			Error::RasterOutOfBounds { pin } => write!(f, "Raster prints on pin {}, outside of the loaded media's printable area", pin),
+			Error::RasterTooLong { lines, max } => write!(f, "Page has {} raster lines but the loaded die-cut label fits at most {}", lines, max),
+			Error::LabelDefinition(message) => write!(f, "Invalid label definition: {}", message),
+			Error::Io(err) => write!(f, "I/O error: {}", err),
+			Error::Image(err) => write!(f, "Image error: {}", err),
+			Error::Rasterization(message) => write!(f, "Rasterization error: {}", message),
+			Error::Unsupported(message) => write!(f, "Unsupported: {}", message),
+			Error::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+			Error::Timeout => write!(f, "Timed out waiting for printer"),
+			Error::Cancelled => write!(f, "Operation cancelled"),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Usb(err) => Some(err),
+			Error::Io(err) => Some(err),
+			Error::Image(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<rusb::Error> for Error {
+	fn from(err: rusb::Error) -> Self {
+		Error::Usb(err)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Self {
+		Error::Io(err)
+	}
+}
+
+impl From<image::ImageError> for Error {
+	fn from(err: image::ImageError) -> Self {
+		Error::Image(err)
+	}
+}
+
+impl From<PrinterError> for Error {
+	fn from(errors: PrinterError) -> Self {
+		Error::Printer(errors)
+	}
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,5 +1,4 @@
-#[macro_use]
-extern crate error_chain;
-
+pub mod error;
 pub mod text;
+pub mod raster;
 pub mod printer;
@@ -2,16 +2,19 @@
 //!
 //! Based on the published [Brother QL Series Command Reference](https://download.brother.com/welcome/docp000678/cv_qlseries_eng_raster_600.pdf)
 
-use std::time::Duration;
-use std::thread;
+use std::time::{ Duration, Instant };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Mutex, MutexGuard };
+use std::thread::{ self, ThreadId };
 
 pub mod constants;
+pub mod geometry;
+pub mod job;
+pub mod options;
+pub mod settings;
+pub mod template;
 
-error_chain! {
-	foreign_links {
-		USB(rusb::Error);
-	}
-}
+pub use crate::error::{ Error, Result };
 
 #[allow(non_snake_case)]
 pub mod status {
@@ -22,8 +25,12 @@ pub mod status {
 	//! * Loaded media
 	//! * Current operation
 	//! * Any errors that have occurred
+	use std::fmt;
+	use bitflags::bitflags;
 	use super::constants::*;
-	#[derive(Debug)]
+	use super::{ Error, Result };
+	#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 	pub enum MediaType {
 		None,
 		ContinuousTape,
@@ -31,24 +38,29 @@ pub mod status {
 	}
 
 	#[derive(Debug)]
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 	pub struct Media {
 		pub media_type: MediaType,
 		pub width: u8,
 		pub length: u8,
 	}
 	impl Media {
-		pub fn to_label(&self) -> Label {
+		pub fn to_label(&self) -> Result<Label> {
+			if self.media_type == MediaType::None {
+				return Err(Error::NoMedia);
+			}
 			let length = if self.length == 0 {
 				None
 			}
 			else {
 				Some(self.length)
 			};
-			label_data(self.width, length).expect("Printer reported invalid label dimensions")
+			label_data(self.width, length).ok_or(Error::UnknownMedia { width: self.width, length: self.length })
 		}
 	}
 
 	#[derive(Debug, PartialEq)]
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 	pub enum StatusType {
 		ReplyToStatusRequest,
 		PrintingCompleted,
@@ -57,17 +69,191 @@ pub mod status {
 		PhaseChange,
 	}
 
+	/// What the printer is currently doing
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+	pub enum Phase {
+		/// Waiting for or receiving print data
+		Receiving,
+		Printing,
+	}
+
+	/// Additional information sent along with a `StatusType::Notification` status
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+	pub enum Notification {
+		None,
+		CoolingStarted,
+		CoolingFinished,
+	}
+
+	bitflags! {
+		/// Errors reported by the printer in its status message
+		///
+		/// The low byte holds the flags of "error information 1" and the high byte those of "error information 2".
+		pub struct PrinterError: u16 {
+			const NO_MEDIA           = 0x0001;
+			const END_OF_MEDIA       = 0x0002;
+			const CUTTER_JAM         = 0x0004;
+			const MAIN_UNIT_IN_USE   = 0x0010;
+			const FAN_FAILURE        = 0x0080;
+			const TRANSMISSION_ERROR = 0x0400;
+			const COVER_OPEN         = 0x1000;
+			const CANNOT_FEED        = 0x4000;
+			const SYSTEM_ERROR       = 0x8000;
+		}
+	}
+	impl PrinterError {
+		/// Decodes the two error information bytes of a status message
+		pub fn from_status_bytes(error_info_1: u8, error_info_2: u8) -> Self {
+			PrinterError::from_bits_truncate(u16::from_le_bytes([error_info_1, error_info_2]))
+		}
+
+		/// Whether every error that is set can be fixed by the user, i.e. by closing the cover or loading new media
+		pub fn is_recoverable(&self) -> bool {
+			let recoverable = PrinterError::NO_MEDIA | PrinterError::END_OF_MEDIA | PrinterError::COVER_OPEN | PrinterError::CANNOT_FEED;
+			!self.is_empty() && recoverable.contains(*self)
+		}
+
+		/// Human-readable descriptions of each error that is set
+		pub fn messages(&self) -> Vec<&'static str> {
+			const MESSAGES: [(PrinterError, &str); 9] = [
+				(PrinterError::NO_MEDIA, "No media when printing"),
+				(PrinterError::END_OF_MEDIA, "End of media"),
+				(PrinterError::CUTTER_JAM, "Tape cutter jam"),
+				(PrinterError::MAIN_UNIT_IN_USE, "Main unit in use"),
+				(PrinterError::FAN_FAILURE, "Fan doesn't work"),
+				(PrinterError::TRANSMISSION_ERROR, "Transmission error"),
+				(PrinterError::COVER_OPEN, "Cover open"),
+				(PrinterError::CANNOT_FEED, "Cannot feed"),
+				(PrinterError::SYSTEM_ERROR, "System error"),
+			];
+			MESSAGES.iter()
+				.filter(|(flag, _)| self.contains(*flag))
+				.map(|(_, message)| *message)
+				.collect()
+		}
+	}
+	#[cfg(feature = "serde")]
+	const ERROR_NAMES: [&str; 9] = [
+		"no_media", "end_of_media", "cutter_jam", "main_unit_in_use", "fan_failure",
+		"transmission_error", "cover_open", "cannot_feed", "system_error",
+	];
+	#[cfg(feature = "serde")]
+	const ERROR_FLAGS: [PrinterError; 9] = [
+		PrinterError::NO_MEDIA, PrinterError::END_OF_MEDIA, PrinterError::CUTTER_JAM, PrinterError::MAIN_UNIT_IN_USE, PrinterError::FAN_FAILURE,
+		PrinterError::TRANSMISSION_ERROR, PrinterError::COVER_OPEN, PrinterError::CANNOT_FEED, PrinterError::SYSTEM_ERROR,
+	];
+	/// Serialized as a list of the names of the errors that are set, e.g. `["end_of_media", "cover_open"]`
+	#[cfg(feature = "serde")]
+	impl serde::Serialize for PrinterError {
+		fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+			serializer.collect_seq(ERROR_FLAGS.iter()
+				.zip(ERROR_NAMES.iter())
+				.filter(|(flag, _)| self.contains(**flag))
+				.map(|(_, name)| *name))
+		}
+	}
+	#[cfg(feature = "serde")]
+	impl<'de> serde::Deserialize<'de> for PrinterError {
+		fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+			let names: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+			names.iter().try_fold(PrinterError::empty(), |errors, name| {
+				ERROR_NAMES.iter()
+					.position(|error_name| error_name == name)
+					.map(|index| errors | ERROR_FLAGS[index])
+					.ok_or_else(|| serde::de::Error::unknown_variant(name, &ERROR_NAMES))
+			})
+		}
+	}
+
+	impl fmt::Display for PrinterError {
+		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			if self.is_empty() {
+				write!(f, "No errors")
+			}
+			else {
+				write!(f, "{}", self.messages().join(", "))
+			}
+		}
+	}
+
+	/// Length of a status message in bytes
+	pub(crate) const FRAME_SIZE: usize = 32;
+
 	#[derive(Debug)]
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 	pub struct Response {
-		pub model: &'static str,
+		/// `None` if the printer reported a model code this crate doesn't know about
+		pub model: Option<Model>,
 		pub status_type: StatusType,
-		pub errors: Vec<&'static str>,
+		pub errors: PrinterError,
 		pub media: Media,
+		pub phase: Phase,
+		pub notification: Notification,
+	}
+	impl Response {
+		/// Decodes a status message as read from the printer
+		pub(crate) fn from_bytes(response: &[u8]) -> Result<Self> {
+			if response.len() != FRAME_SIZE || response[0] != 0x80 {
+				return Err(Error::Protocol("Invalid response received from printer"));
+			}
+
+			let model = Model::from_status_code(response[4]);
+
+			let errors = PrinterError::from_status_bytes(response[8], response[9]);
+
+			let width = response[10];
+			let length = response[17];
+
+			let media_type = match response[11] {
+				0x0A => MediaType::ContinuousTape,
+				0x0B => MediaType::DieCutLabels,
+				_    => MediaType::None,
+			};
+
+			let status_type = match response[18] {
+				0x00 => StatusType::ReplyToStatusRequest,
+				0x01 => StatusType::PrintingCompleted,
+				0x02 => StatusType::ErrorOccurred,
+				0x05 => StatusType::Notification,
+				0x06 => StatusType::PhaseChange,
+				// Will never occur
+				_ => StatusType::Notification
+			};
+
+			let phase = match response[19] {
+				0x01 => Phase::Printing,
+				_    => Phase::Receiving,
+			};
+
+			let notification = match response[22] {
+				0x03 => Notification::CoolingStarted,
+				0x04 => Notification::CoolingFinished,
+				_    => Notification::None,
+			};
+
+			Ok(Response {
+				model,
+				status_type,
+				errors,
+				media: Media {
+					media_type,
+					width,
+					length,
+				},
+				phase,
+				notification,
+			})
+		}
 	}
 }
 
 fn printer_filter<T: rusb::UsbContext>(device: &rusb::Device<T>) -> bool {
-	let descriptor = device.device_descriptor().unwrap();
+	let descriptor = match device.device_descriptor() {
+		Ok(descriptor) => descriptor,
+		Err(_) => return false,
+	};
 	if descriptor.vendor_id() == constants::VENDOR_ID && descriptor.product_id() == 0x2049 {
 		eprintln!("You must disable Editor Lite mode on your QL-700 before you can print with it");
 	}
@@ -75,24 +261,145 @@ fn printer_filter<T: rusb::UsbContext>(device: &rusb::Device<T>) -> bool {
 }
 
 /// Get a vector of all attached and supported Brother QL printers as USB devices from which `ThermalPrinter` structs can be initialized.
-pub fn printers() -> Vec<rusb::Device<rusb::GlobalContext>> {
-	rusb::DeviceList::new()
-		.unwrap()
+pub fn printers() -> Result<Vec<rusb::Device<rusb::GlobalContext>>> {
+	Ok(rusb::DeviceList::new()?
 		.iter()
 		.filter(printer_filter)
-		.collect()
+		.collect())
 }
 
-const RASTER_LINE_LENGTH: u8 = 90;
+/// A single line of pixels as sent to the printer, one bit per print head pin starting with the most significant
+/// bit of the first byte. Its length is `PrintHead::bytes_per_line()` of the printer's print head.
+pub type RasterLine = Vec<u8>;
+
+/// How long the blocking print functions wait for the printer to finish
+const PRINT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Progress updates reported by `ThermalPrinter::wait_for_completion()` and
+/// `ThermalPrinter::print_job_blocking_with_progress()`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Progress {
+	/// The printer switched between receiving data and printing
+	PhaseChanged(status::Phase),
+	/// The printer finished printing a page
+	PageCompleted {
+		completed: usize,
+		total: usize,
+	},
+	/// Printing the zero-based `page` of a job failed with errors a person can fix, e.g. by closing the cover or
+	/// loading new media. The job waits for them to be cleared.
+	Recovering {
+		page: usize,
+		errors: status::PrinterError,
+	},
+	/// The errors were cleared and the printer was reset, discarding whatever it had received of `page`. The page
+	/// is sent again from the start.
+	Resumed {
+		page: usize,
+	},
+}
+
+/// The automatic status notification command (`ESC i !`)
+fn notification_command(enabled: bool) -> [u8; 4] {
+	[0x1B, 0x69, 0x21, if enabled { 0 } else { 1 }]
+}
+
+/// Waits until the status messages returned by `next_status` report that `pages` pages have been printed. See
+/// `ThermalPrinter::wait_for_completion()`.
+fn await_pages<N, C, F>(pages: usize, timeout: Duration, mut next_status: N, cancelled: C, mut progress: F) -> Result<()>
+where
+	N: FnMut(Duration) -> Result<Option<status::Response>>,
+	C: Fn() -> bool,
+	F: FnMut(Progress),
+{
+	// Poll in short intervals so that cancellation is noticed promptly
+	const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+	let deadline = Instant::now() + timeout;
+	let mut completed = 0;
+	while completed < pages {
+		if cancelled() {
+			return Err(Error::Cancelled);
+		}
+		let now = Instant::now();
+		if now >= deadline {
+			return Err(Error::Timeout);
+		}
+
+		let response = match next_status(POLL_INTERVAL.min(deadline - now))? {
+			Some(response) => response,
+			None => continue,
+		};
+		if response.status_type == status::StatusType::ErrorOccurred && response.errors.is_empty() {
+			return Err(Error::Protocol("Printer reported an error without any error flags"));
+		}
+		if !response.errors.is_empty() {
+			return Err(Error::Printer(response.errors));
+		}
+		match response.status_type {
+			status::StatusType::PhaseChange => progress(Progress::PhaseChanged(response.phase)),
+			status::StatusType::PrintingCompleted => {
+				completed += 1;
+				progress(Progress::PageCompleted { completed, total: pages });
+			},
+			_ => {},
+		}
+	}
+	Ok(())
+}
+
+/// Whether an operation was cancelled with `ThermalPrinter::cancel()`, or through a caller-owned flag, since it
+/// started
+struct Cancellation<'a> {
+	cancellations: &'a AtomicUsize,
+	generation: usize,
+	flag: Option<&'a AtomicBool>,
+}
+impl<'a> Cancellation<'a> {
+	fn new(cancellations: &'a AtomicUsize, flag: Option<&'a AtomicBool>) -> Self {
+		Cancellation {
+			cancellations,
+			generation: cancellations.load(Ordering::SeqCst),
+			flag,
+		}
+	}
+
+	fn is_cancelled(&self) -> bool {
+		self.cancellations.load(Ordering::SeqCst) != self.generation
+			|| self.flag.is_some_and(|flag| flag.load(Ordering::SeqCst))
+	}
+}
+
+/// Holds `ThermalPrinter::sending` and records which thread holds it
+struct SendingGuard<'a> {
+	_lock: MutexGuard<'a, ()>,
+	thread: &'a Mutex<Option<ThreadId>>,
+}
+impl<'a> Drop for SendingGuard<'a> {
+	fn drop(&mut self) {
+		// Cleared before the lock is released, since fields are dropped after this
+		*self.thread.lock().unwrap_or_else(|err| err.into_inner()) = None;
+	}
+}
 
 /// The primary interface for dealing with Brother QL printers. Handles all USB communication with the printer.
 pub struct ThermalPrinter<T: rusb::UsbContext> {
 	pub manufacturer: String,
 	pub model: String,
 	pub serial_number: String,
+	product_id: u16,
 	handle: rusb::DeviceHandle<T>,
 	in_endpoint: u8,
 	out_endpoint: u8,
+	/// Held while a job is being sent to the printer or waited on
+	sending: Mutex<()>,
+	/// The thread holding `sending`, so that `cancel()` and `reset()` called from a progress callback don't wait
+	/// for a lock their own thread holds
+	sending_thread: Mutex<Option<ThreadId>>,
+	/// Incremented by `cancel()`, which interrupts every operation that started before
+	cancellations: AtomicUsize,
+	/// Whether automatic status notifications were turned off with `set_status_notification()`
+	notifications_disabled: AtomicBool,
 }
 impl<T: rusb::UsbContext> std::fmt::Debug for ThermalPrinter<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -109,20 +416,21 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
 		let mut out_endpoint: Option<u8> = None;
 
 		let config = device.active_config_descriptor()?;
-		let interface = config.interfaces().next().chain_err(|| "Brother QL printers should have exactly one interface")?;
-		let interface_descriptor = interface.descriptors().next().chain_err(|| "Brother QL printers should have exactly one interface descriptor")?;
+		let interface = config.interfaces().next().ok_or(Error::Protocol("Brother QL printers should have exactly one interface"))?;
+		let interface_descriptor = interface.descriptors().next().ok_or(Error::Protocol("Brother QL printers should have exactly one interface descriptor"))?;
 		for endpoint in interface_descriptor.endpoint_descriptors() {
 			if endpoint.transfer_type() != rusb::TransferType::Bulk {
-				bail!("Brother QL printers are defined as using only bulk endpoint communication");
+				return Err(Error::Protocol("Brother QL printers are defined as using only bulk endpoint communication"));
 			}
 			match endpoint.direction() {
 				rusb::Direction::In  => in_endpoint  = Some(endpoint.address()),
 				rusb::Direction::Out => out_endpoint = Some(endpoint.address()),
 			}
 		}
-		if in_endpoint.is_none() || out_endpoint.is_none() {
-			bail!("Input or output endpoint not found");
-		}
+		let (in_endpoint, out_endpoint) = match (in_endpoint, out_endpoint) {
+			(Some(in_endpoint), Some(out_endpoint)) => (in_endpoint, out_endpoint),
+			_ => return Err(Error::Protocol("Input or output endpoint not found")),
+		};
 
 		handle.claim_interface(interface.number())?;
 		if let Ok(kd_active) = handle.kernel_driver_active(interface.number()) {
@@ -137,19 +445,81 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
 			manufacturer: handle.read_manufacturer_string_ascii(&device_descriptor)?,
 			model: handle.read_product_string_ascii(&device_descriptor)?,
 			serial_number: handle.read_serial_number_string_ascii(&device_descriptor)?,
+			product_id: device_descriptor.product_id(),
 			handle,
-			in_endpoint: in_endpoint.unwrap(),
-			out_endpoint: out_endpoint.unwrap(),
+			in_endpoint,
+			out_endpoint,
+			sending: Mutex::new(()),
+			sending_thread: Mutex::new(None),
+			cancellations: AtomicUsize::new(0),
+			notifications_disabled: AtomicBool::new(false),
 		};
 
-		// Reset printer
+		printer.reset()?;
+		Ok(printer)
+	}
+
+	/// Resets the printer to a freshly initialized state and returns its status.
+	///
+	/// Any data left in the printer's buffer is flushed with the invalidate command and the printer is re-initialized.
+	/// If a job is currently being sent or waited on from another thread, this waits until that's done; use
+	/// `cancel()` to interrupt it instead. Called from the `progress` callback of the job's own thread, the printer
+	/// is reset right away.
+	pub fn reset(&self) -> Result<status::Response> {
+		if self.sending_on_this_thread() {
+			return self.reset_unlocked();
+		}
+		let _sending = self.lock_sending();
+		self.reset_unlocked()
+	}
+
+	/// Interrupts a job that is being sent or waited on from another thread (e.g. by `print()`,
+	/// `print_blocking()`, `print_job_blocking()`, or `wait_for_completion()`) and resets the printer.
+	///
+	/// The interrupted call returns `Error::Cancelled`. Pages the printer has fully received may still be printed.
+	/// This can also be called from the `progress` callback of `wait_for_completion()` or
+	/// `print_job_blocking_with_progress()`, in which case the call the callback belongs to is interrupted as soon as
+	/// the callback returns.
+	pub fn cancel(&self) -> Result<status::Response> {
+		self.cancellations.fetch_add(1, Ordering::SeqCst);
+		// The interrupted call on this thread is paused in its callback, so nothing else is reading
+		if self.sending_on_this_thread() {
+			return self.reset_unlocked();
+		}
+		// Interrupted calls release the lock once they notice, so the reset can't race with their reads
+		let _sending = self.lock_sending();
+		self.reset_unlocked()
+	}
+
+	fn cancellation<'a>(&'a self, flag: Option<&'a AtomicBool>) -> Cancellation<'a> {
+		Cancellation::new(&self.cancellations, flag)
+	}
+
+	fn reset_unlocked(&self) -> Result<status::Response> {
 		let clear_command = [0x00; 200];
-		ThermalPrinter::write(&printer, &clear_command)?;
+		self.write(&clear_command)?;
 		let initialize_command = [0x1B, 0x40];
-		ThermalPrinter::write(&printer, &initialize_command)?;
+		self.write(&initialize_command)?;
 
-		ThermalPrinter::get_status(&printer)?;
-		Ok(printer)
+		// Discard status messages still queued from before the reset
+		while self.read_timeout(Duration::from_millis(50)).is_ok() {}
+
+		self.get_status()
+	}
+
+	fn lock_sending(&self) -> SendingGuard<'_> {
+		// The guarded data is empty so a panic while sending can't leave it inconsistent
+		let lock = self.sending.lock().unwrap_or_else(|err| err.into_inner());
+		*self.sending_thread.lock().unwrap_or_else(|err| err.into_inner()) = Some(thread::current().id());
+		SendingGuard {
+			_lock: lock,
+			thread: &self.sending_thread,
+		}
+	}
+
+	/// Whether `sending` is held by the current thread, i.e. this was called from a progress callback
+	fn sending_on_this_thread(&self) -> bool {
+		*self.sending_thread.lock().unwrap_or_else(|err| err.into_inner()) == Some(thread::current().id())
 	}
 
 	/// Sends raster lines to the USB printer, begins printing, and immediately returns
@@ -161,62 +531,163 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
 	/// printer can print out-of-bounds and even print on parts of the label not originally intended to
 	/// contain content. Your rasterizer will have to figure out, given a media type, which parts of the
 	/// image will appear on the media and resize or shift margins and content accordingly.
-	pub fn print(&self, raster_lines: Vec<[u8; RASTER_LINE_LENGTH as usize]>) -> Result<status::Response> {
+	/// `Label::head_offset()` gives the pin at which a label's printable area starts on each print head.
+	pub fn print(&self, raster_lines: Vec<RasterLine>) -> Result<status::Response> {
+		self.print_with_options(raster_lines, &options::PrintOptions::default())
+	}
+	/// Same as `print()` but with control over cutting, resolution, margins, and the printer's own validation and
+	/// recovery behavior.
+	pub fn print_with_options(&self, raster_lines: Vec<RasterLine>, options: &options::PrintOptions) -> Result<status::Response> {
+		let cancellation = self.cancellation(None);
+		let _sending = self.lock_sending();
+		self.print_unlocked(raster_lines, options, &cancellation)
+	}
+
+	fn print_unlocked(&self, raster_lines: Vec<RasterLine>, options: &options::PrintOptions, cancellation: &Cancellation<'_>) -> Result<status::Response> {
 		let status = self.get_status()?;
+		let pages = [raster_lines];
+		let label = job::check_status(&status, &pages, None, self.print_head(), options)?;
 
+		self.send_page(&pages[0], &status.media, &label, options, 0, true, cancellation)?;
+		self.read()
+	}
+
+	/// Sends the commands and raster lines for a single page. The last page of a job is printed with feeding.
+	#[allow(clippy::too_many_arguments)]
+	fn send_page(&self, raster_lines: &[RasterLine], media: &status::Media, label: &constants::Label, options: &options::PrintOptions, page_index: usize, last_page: bool, cancellation: &Cancellation<'_>) -> Result<()> {
 		let mode_command = [0x1B, 0x69, 0x61, 1];
 		self.write(&mode_command)?;
 
-		const VALID_FLAGS: u8 = 0x80 | 0x02 | 0x04 | 0x08 | 0x40; // Everything enabled
-		let media_type: u8 = match status.media.media_type {
+		let media_type: u8 = match media.media_type {
 			status::MediaType::ContinuousTape => 0x0A,
 			status::MediaType::DieCutLabels => 0x0B,
-			_ => return Err("No media loaded into printer".into())
+			_ => return Err(Error::NoMedia)
 		};
+		let starting_page = if page_index == 0 { 0 } else { 1 };
 
-		let mut media_command = [0x1B, 0x69, 0x7A, VALID_FLAGS, media_type, status.media.width, status.media.length, 0, 0, 0, 0, 0x01, 0];
+		let mut media_command = [0x1B, 0x69, 0x7A, options.print_information_flags(), media_type, media.width, media.length, 0, 0, 0, 0, starting_page, 0];
 		let line_count = (raster_lines.len() as u32).to_le_bytes();
 		media_command[7..7 + 4].copy_from_slice(&line_count);
 		self.write(&media_command)?;
 
-		self.write(&[0x1B, 0x69, 0x4D, 1 << 6])?; // Enable auto-cut
-		self.write(&[0x1B, 0x69, 0x4B, 1 << 3 | 0 << 6])?; // Enable cut-at-end and disable high res printing
-
-		let label = self.current_label()?;
+		self.write(&[0x1B, 0x69, 0x4D, options.various_mode()])?; // Auto-cut and mirror printing
+		self.write(&[0x1B, 0x69, 0x4B, options.expanded_mode()])?; // Cut-at-end and high res printing
 
-		let margins_command = [0x1B, 0x69, 0x64, label.feed_margin, 0];
+		let margin = options.margin.unwrap_or(label.feed_margin as u16);
+		let mut margins_command = [0x1B, 0x69, 0x64, 0, 0];
+		margins_command[3..5].copy_from_slice(&margin.to_le_bytes());
 		self.write(&margins_command)?;
 
 		for line in raster_lines.iter() {
-			let mut raster_command = vec![0x67, 0x00, RASTER_LINE_LENGTH];
+			if cancellation.is_cancelled() {
+				return Err(Error::Cancelled);
+			}
+			let mut raster_command = vec![0x67, 0x00, line.len() as u8];
 			raster_command.extend_from_slice(line);
 			self.write(&raster_command)?;
 		}
 
-		let print_command = [0x1A];
-		self.write(&print_command)?;
-
-		self.read()
+		let print_command = if last_page {
+			[0x1A] // Print with feeding
+		}
+		else {
+			[0x0C] // Print without feeding
+		};
+		self.write(&print_command)
 	}
 	/// Same as `print()` but will not return until the printer reports that it has finished printing.
-	pub fn print_blocking(&self, raster_lines: Vec<[u8; RASTER_LINE_LENGTH as usize]>) -> Result<()> {
-		self.print(raster_lines)?;
-		loop {
-			match self.read() {
-				Ok(ref response) if response.status_type == status::StatusType::PrintingCompleted => break,
-				_ => thread::sleep(Duration::from_millis(50)),
-			}
+	pub fn print_blocking(&self, raster_lines: Vec<RasterLine>) -> Result<()> {
+		self.print_blocking_with_options(raster_lines, &options::PrintOptions::default())
+	}
+	/// Same as `print_with_options()` but will not return until the printer reports that it has finished printing.
+	pub fn print_blocking_with_options(&self, raster_lines: Vec<RasterLine>, options: &options::PrintOptions) -> Result<()> {
+		let cancellation = self.cancellation(None);
+		let _sending = self.lock_sending();
+		self.with_notifications(|| {
+			self.print_unlocked(raster_lines, options, &cancellation)?;
+			self.wait_for_pages(1, PRINT_TIMEOUT, &cancellation, |_| {})
+		})
+	}
+
+	/// Waits until the printer reports that `pages` pages have been printed.
+	///
+	/// `progress` is called for every phase change and completed page. Waiting stops with an error as soon as the
+	/// printer reports one (e.g. `Error::Printer` with `PrinterError::COVER_OPEN`), once `timeout` has elapsed
+	/// (`Error::Timeout`), or when `cancel` is set or `cancel()` is called (`Error::Cancelled`). Setting `cancel`
+	/// only stops waiting; the printer will keep printing whatever it has already received.
+	///
+	/// Automatic status notifications are turned on while waiting, even if they were disabled with
+	/// `set_status_notification()`. Pages that finished printing while they were disabled aren't counted. Other
+	/// threads can't send to the printer until waiting stops, but `progress` may call `cancel()` to stop waiting.
+	pub fn wait_for_completion<F: FnMut(Progress)>(&self, pages: usize, timeout: Duration, cancel: Option<&AtomicBool>, progress: F) -> Result<()> {
+		let cancellation = self.cancellation(cancel);
+		let _sending = self.lock_sending();
+		self.with_notifications(|| self.wait_for_pages(pages, timeout, &cancellation, progress))
+	}
+
+	fn wait_for_pages<F: FnMut(Progress)>(&self, pages: usize, timeout: Duration, cancellation: &Cancellation<'_>, progress: F) -> Result<()> {
+		let mut events = self.status_events();
+		await_pages(
+			pages,
+			timeout,
+			|timeout| events.next_timeout(timeout),
+			|| cancellation.is_cancelled(),
+			progress,
+		)
+	}
+
+	/// Runs `f` with automatic status notifications turned on, since waiting for completion relies on them, and
+	/// turns them off again afterwards if they were disabled with `set_status_notification()`
+	fn with_notifications<R, F: FnOnce() -> Result<R>>(&self, f: F) -> Result<R> {
+		self.write(&notification_command(true))?;
+		let result = f();
+		if self.notifications_disabled.load(Ordering::SeqCst) {
+			let restored = self.write(&notification_command(false));
+			return result.and_then(|value| restored.map(|_| value));
 		}
+		result
+	}
+
+	/// Controls whether the printer automatically sends status messages when its state changes (e.g. phase
+	/// changes, errors, and print completion). Printers ship with this enabled.
+	///
+	/// `status_events()` relies on these automatic status messages.
+	pub fn set_status_notification(&self, enabled: bool) -> Result<()> {
+		self.write(&notification_command(enabled))?;
+		self.notifications_disabled.store(!enabled, Ordering::SeqCst);
 		Ok(())
 	}
 
+	/// Returns an iterator over status messages as they are pushed by the printer.
+	///
+	/// Iterating blocks until the printer sends its next status message. Use `StatusEvents::next_timeout()` to
+	/// wait for a bounded amount of time instead.
+	pub fn status_events(&self) -> StatusEvents<'_, T> {
+		StatusEvents {
+			printer: self,
+		}
+	}
+
+	/// Get the printer's model from its USB Product ID. Returns `None` for printers this crate doesn't know about.
+	pub fn printer_model(&self) -> Option<constants::Model> {
+		constants::Model::from_product_id(self.product_id)
+	}
+
+	/// Get the print head of the printer, which determines the length of raster lines it accepts. Printers this
+	/// crate doesn't know about are assumed to have a standard print head.
+	pub fn print_head(&self) -> geometry::PrintHead {
+		self.printer_model()
+			.map(|model| model.print_head())
+			.unwrap_or(geometry::PrintHead::Standard)
+	}
+
 	/// Get the currently loaded label size.
 	pub fn current_label(&self) -> Result<constants::Label> {
 		let media = self.get_status()?.media;
 		constants::label_data(media.width, match media.length {
 			0 => None,
 			_ => Some(media.length)
-		}).ok_or("Unknown media loaded in printer".into())
+		}).ok_or(Error::UnknownMedia { width: media.width, length: media.length })
 	}
 
 	/// Get the current status of the printer including possible errors, media type, and model name.
@@ -227,72 +698,13 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
 	}
 
 	fn read(&self) -> Result<status::Response> {
-		const RECEIVE_SIZE: usize = 32;
-		let mut response = [0; RECEIVE_SIZE];
-		let bytes_read = self.handle.read_bulk(self.in_endpoint, &mut response, Duration::from_millis(500))?;
-
-		if bytes_read != RECEIVE_SIZE || response[0] != 0x80 {
-			return Err("Invalid response received from printer".into());
-		}
-
-		let model = match response[4] {
-			0x4F => "QL-500/550",
-			0x31 => "QL-560",
-			0x32 => "QL-570",
-			0x33 => "QL-580N",
-			0x51 => "QL-650TD",
-			0x35 => "QL-700",
-			0x50 => "QL-1050",
-			0x34 => "QL-1060N",
-			_ => "Unknown"
-		};
-
-		let mut errors = Vec::new();
-
-		fn error_if(byte: u8, flag: u8, message: &'static str, errors: &mut Vec<&'static str>) {
-			if byte & flag != 0 {
-				errors.push(message);
-			}
-		}
-		error_if(response[8], 0x01, "No media when printing", &mut errors);
-		error_if(response[8], 0x02, "End of media", &mut errors);
-		error_if(response[8], 0x04, "Tape cutter jam", &mut errors);
-		error_if(response[8], 0x10, "Main unit in use", &mut errors);
-		error_if(response[8], 0x80, "Fan doesn't work", &mut errors);
-		error_if(response[9], 0x04, "Transmission error", &mut errors);
-		error_if(response[9], 0x10, "Cover open", &mut errors);
-		error_if(response[9], 0x40, "Cannot feed", &mut errors);
-		error_if(response[9], 0x80, "System error", &mut errors);
-
-		let width = response[10];
-		let length = response[17];
-
-		let media_type = match response[11] {
-			0x0A => status::MediaType::ContinuousTape,
-			0x0B => status::MediaType::DieCutLabels,
-			_    => status::MediaType::None,
-		};
-
-		let status_type = match response[18] {
-			0x00 => status::StatusType::ReplyToStatusRequest,
-			0x01 => status::StatusType::PrintingCompleted,
-			0x02 => status::StatusType::ErrorOccurred,
-			0x05 => status::StatusType::Notification,
-			0x06 => status::StatusType::PhaseChange,
-			// Will never occur
-			_ => status::StatusType::Notification
-		};
+		self.read_timeout(Duration::from_millis(500))
+	}
 
-		Ok(status::Response {
-			model,
-			status_type,
-			errors,
-			media: status::Media {
-				media_type,
-				width,
-				length,
-			}
-		})
+	fn read_timeout(&self, timeout: Duration) -> Result<status::Response> {
+		let mut response = [0; status::FRAME_SIZE];
+		let bytes_read = self.handle.read_bulk(self.in_endpoint, &mut response, timeout)?;
+		status::Response::from_bytes(&response[..bytes_read])
 	}
 
 	fn write(&self, data: &[u8]) -> Result<()> {
@@ -301,38 +713,235 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
 	}
 }
 
+/// Status messages pushed by the printer, obtained from `ThermalPrinter::status_events()`
+pub struct StatusEvents<'a, T: rusb::UsbContext> {
+	printer: &'a ThermalPrinter<T>,
+}
+impl<'a, T: rusb::UsbContext> StatusEvents<'a, T> {
+	/// Waits up to `timeout` for the next status message. Returns `Ok(None)` if the printer sent nothing in that time.
+	pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<status::Response>> {
+		match self.printer.read_timeout(timeout) {
+			Ok(response) => Ok(Some(response)),
+			Err(Error::Usb(rusb::Error::Timeout)) => Ok(None),
+			Err(err) => Err(err),
+		}
+	}
+}
+impl<'a, T: rusb::UsbContext> Iterator for StatusEvents<'a, T> {
+	type Item = Result<status::Response>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			match self.next_timeout(Duration::from_secs(1)) {
+				Ok(Some(response)) => return Some(Ok(response)),
+				Ok(None) => continue,
+				Err(err) => return Some(Err(err)),
+			}
+		}
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::printer::{ printers, ThermalPrinter };
 	#[test]
+	#[ignore]
 	fn connect() {
-		let printer_list = printers();
-		assert!(printer_list.len() > 0, "No printers found");
-		let mut printer = ThermalPrinter::new(printer_list.into_iter().next().unwrap()).unwrap();
-		printer.init().unwrap();
+		let printer_list = printers().unwrap();
+		assert!(!printer_list.is_empty(), "No printers found");
+		let printer = ThermalPrinter::new(printer_list.into_iter().next().unwrap()).unwrap();
+		printer.get_status().unwrap();
+	}
+
+	use crate::printer::status::PrinterError;
+	#[test]
+	fn decode_errors() {
+		let errors = PrinterError::from_status_bytes(0x02, 0x10);
+		assert_eq!(errors, PrinterError::END_OF_MEDIA | PrinterError::COVER_OPEN);
+		assert_eq!(errors.to_string(), "End of media, Cover open");
+		assert!(PrinterError::from_status_bytes(0, 0).is_empty());
+		assert!(errors.is_recoverable());
+		assert!(!(errors | PrinterError::CUTTER_JAM).is_recoverable());
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn serialize_status() {
+		let response = status_with(PrinterError::END_OF_MEDIA | PrinterError::COVER_OPEN, status::MediaType::ContinuousTape, 62, 0);
+		let json = serde_json::to_value(&response).unwrap();
+		assert_eq!(json, serde_json::json!({
+			"model": "QL-700",
+			"status_type": "reply_to_status_request",
+			"errors": ["end_of_media", "cover_open"],
+			"media": { "media_type": "continuous_tape", "width": 62, "length": 0 },
+			"phase": "receiving",
+			"notification": "none",
+		}));
+		let parsed: status::Response = serde_json::from_value(json).unwrap();
+		assert_eq!(parsed.errors, response.errors);
+
+		let label = constants::label_data(62, None).unwrap();
+		let json = serde_json::to_string(&label).unwrap();
+		assert_eq!(json, r#"{"tape_size":[62,0],"dots":[732,0],"dots_printable":[696,0],"right_margin":12,"feed_margin":35,"shape":"rectangle"}"#);
+		assert_eq!(serde_json::from_str::<constants::Label>(&json).unwrap(), label);
+	}
+
+	use std::collections::VecDeque;
+	use std::time::Duration;
+	use crate::printer::{ await_pages, notification_command, Cancellation, Progress };
+	/// A status message as a QL-700 with 62mm continuous tape sends it
+	fn status_frame(status_type: u8, phase: u8, errors: [u8; 2]) -> [u8; 32] {
+		let mut frame = [0; 32];
+		frame[..4].copy_from_slice(&[0x80, 0x20, 0x42, 0x30]);
+		frame[4] = 0x35;
+		frame[8..10].copy_from_slice(&errors);
+		frame[10] = 62;
+		frame[11] = 0x0A;
+		frame[18] = status_type;
+		frame[19] = phase;
+		frame
+	}
+	/// Waits for `pages` pages while the printer sends `frames`, returning the result and the progress reported
+	fn await_frames(pages: usize, frames: &[[u8; 32]]) -> (crate::error::Result<()>, Vec<Progress>) {
+		let mut frames: VecDeque<_> = frames.iter().map(|frame| status::Response::from_bytes(frame)).collect();
+		let mut progress = Vec::new();
+		let result = await_pages(
+			pages,
+			Duration::from_millis(50),
+			|_| frames.pop_front().transpose(),
+			|| false,
+			|update| progress.push(update),
+		);
+		(result, progress)
+	}
+	#[test]
+	fn status_frames() {
+		let response = status::Response::from_bytes(&status_frame(0x06, 0x01, [0, 0])).unwrap();
+		assert_eq!(response.model, Some(constants::Model::QL700));
+		assert_eq!(response.status_type, status::StatusType::PhaseChange);
+		assert_eq!(response.phase, status::Phase::Printing);
+		assert_eq!(response.media.media_type, status::MediaType::ContinuousTape);
+		assert_eq!((response.media.width, response.media.length), (62, 0));
+
+		let mut notification = status_frame(0x05, 0x00, [0, 0]);
+		notification[22] = 0x03;
+		assert_eq!(status::Response::from_bytes(&notification).unwrap().notification, status::Notification::CoolingStarted);
+
+		assert!(matches!(status::Response::from_bytes(&status_frame(0x00, 0x00, [0, 0])[..31]), Err(Error::Protocol(_))));
+		let mut wrong_header = status_frame(0x00, 0x00, [0, 0]);
+		wrong_header[0] = 0x00;
+		assert!(matches!(status::Response::from_bytes(&wrong_header), Err(Error::Protocol(_))));
+	}
+
+	#[test]
+	fn completion() {
+		let printing = status_frame(0x06, 0x01, [0, 0]);
+		let receiving = status_frame(0x06, 0x00, [0, 0]);
+		let completed = status_frame(0x01, 0x01, [0, 0]);
+		let (result, progress) = await_frames(2, &[printing, completed, receiving, printing, completed]);
+		assert!(result.is_ok());
+		assert_eq!(progress, [
+			Progress::PhaseChanged(status::Phase::Printing),
+			Progress::PageCompleted { completed: 1, total: 2 },
+			Progress::PhaseChanged(status::Phase::Receiving),
+			Progress::PhaseChanged(status::Phase::Printing),
+			Progress::PageCompleted { completed: 2, total: 2 },
+		]);
+
+		// The printer stops sending status messages once it's done, so waiting for more pages times out
+		let (result, _) = await_frames(2, &[printing, completed]);
+		assert!(matches!(result, Err(Error::Timeout)));
+
+		let (result, progress) = await_frames(1, &[printing, status_frame(0x02, 0x00, [0x00, 0x10])]);
+		assert!(matches!(result, Err(Error::Printer(PrinterError::COVER_OPEN))));
+		assert_eq!(progress, [Progress::PhaseChanged(status::Phase::Printing)]);
+		let (result, _) = await_frames(1, &[status_frame(0x02, 0x00, [0, 0])]);
+		assert!(matches!(result, Err(Error::Protocol(_))));
+
+		assert_eq!(notification_command(true), [0x1B, 0x69, 0x21, 0x00]);
+		assert_eq!(notification_command(false), [0x1B, 0x69, 0x21, 0x01]);
+	}
+
+	#[test]
+	fn cancel_while_waiting() {
+		use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+		use std::time::Instant;
+
+		let cancellations = AtomicUsize::new(0);
+		let flag = AtomicBool::new(false);
+		let cancellation = Cancellation::new(&cancellations, Some(&flag));
+		assert!(!cancellation.is_cancelled());
+		flag.store(true, Ordering::SeqCst);
+		assert!(cancellation.is_cancelled());
+
+		// `cancel()` on another thread ends a wait on a printer that never reports completion
+		let cancellation = Cancellation::new(&cancellations, None);
+		let started = Instant::now();
+		let result = std::thread::scope(|scope| {
+			scope.spawn(|| {
+				std::thread::sleep(Duration::from_millis(200));
+				cancellations.fetch_add(1, Ordering::SeqCst);
+			});
+			await_pages(1, Duration::from_secs(30), |timeout| {
+				std::thread::sleep(timeout);
+				Ok(None)
+			}, || cancellation.is_cancelled(), |_| {})
+		});
+		assert!(matches!(result, Err(Error::Cancelled)));
+		assert!(started.elapsed() < Duration::from_secs(5));
+
+		// Operations started after the cancellation aren't affected by it
+		assert!(!Cancellation::new(&cancellations, None).is_cancelled());
+	}
+
+	use crate::printer::{ constants, status, Error };
+	use crate::printer::options::PrintOptions;
+	/// A reply to a status request from a QL-700 with `errors` and the given media loaded
+	pub(crate) fn status_with(errors: PrinterError, media_type: status::MediaType, width: u8, length: u8) -> status::Response {
+		status::Response {
+			model: Some(constants::Model::QL700),
+			status_type: status::StatusType::ReplyToStatusRequest,
+			errors,
+			media: status::Media { media_type, width, length },
+			phase: status::Phase::Receiving,
+			notification: status::Notification::None,
+		}
+	}
+
+	#[test]
+	fn media_labels() {
+		let media = status::Media { media_type: status::MediaType::DieCutLabels, width: 29, length: 90 };
+		assert_eq!(media.to_label().unwrap(), constants::label_data(29, Some(90)).unwrap());
+		let unknown = status::Media { media_type: status::MediaType::DieCutLabels, width: 29, length: 91 };
+		assert!(matches!(unknown.to_label(), Err(Error::UnknownMedia { width: 29, length: 91 })));
+		let none = status::Media { media_type: status::MediaType::None, width: 0, length: 0 };
+		assert!(matches!(none.to_label(), Err(Error::NoMedia)));
 	}
 
 	use std::path::PathBuf;
     #[test]
 	#[ignore]
     fn print() {
-		let printer_list = printers();
-		assert!(printer_list.len() > 0, "No printers found");
-		let mut printer = ThermalPrinter::new(printer_list.into_iter().next().unwrap()).unwrap();
-		let label = printer.init().unwrap().media.to_label();
+		let printer_list = printers().unwrap();
+		assert!(!printer_list.is_empty(), "No printers found");
+		let printer = ThermalPrinter::new(printer_list.into_iter().next().unwrap()).unwrap();
+		let label = printer.get_status().unwrap().media.to_label().unwrap();
 
         let mut rasterizer = crate::text::TextRasterizer::new(
             label,
             PathBuf::from("./Space Mono Bold.ttf")
         );
+        rasterizer.set_print_head(printer.print_head());
         rasterizer.set_second_row_image(PathBuf::from("./logos/BuildGT Mono.png"));
         let lines = rasterizer.rasterize(
             "Ryan Petschek",
             Some("Computer Science"),
 			1.2,
 			false
-        );
+        ).unwrap();
 
-		dbg!(printer.print(lines).unwrap());
+		// The second row image is drawn outside of the label's printable area
+		let options = PrintOptions { check_printable_area: false, ..PrintOptions::default() };
+		dbg!(printer.print_with_options(lines, &options).unwrap());
     }
 }
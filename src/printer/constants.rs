@@ -1,169 +1,846 @@
 //! Label media and USB ID constants used by Brother QL printers
 
-#[derive(Debug, Copy, Clone)]
+use std::borrow::Cow;
+use std::sync::{ Arc, OnceLock, RwLock };
+use super::geometry::PrintHead;
+use super::status::{ Media, MediaType };
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WidthLength(pub u32, pub u32);
 
-#[derive(Debug, Copy, Clone)]
+/// The outline of the printable area of a label
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum LabelShape {
+	Rectangle,
+	/// Round die-cut labels. The printable area is the circle inscribed in `dots_printable`.
+	Round,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Label {
 	pub tape_size: WidthLength,
 	pub dots: WidthLength,
 	pub dots_printable: WidthLength,
 	pub right_margin: u8,
 	pub feed_margin: u8,
+	pub shape: LabelShape,
+}
+
+/// A label roll type in the catalog of supported media
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LabelEntry {
+	/// Brother's part number for the roll, e.g. "DK-11201", if it is sold under one
+	pub part_number: Option<Cow<'static, str>>,
+	/// Human-readable description of the roll
+	pub description: Cow<'static, str>,
+	/// Short identifier used by other tools such as `brother_ql`, e.g. "62" or "29x90"
+	pub identifier: Cow<'static, str>,
+	pub media_type: MediaType,
+	/// Media width in mm as reported in the printer's status
+	pub media_width: u8,
+	/// Media length in mm as reported in the printer's status. `0` for continuous tape.
+	pub media_length: u8,
+	pub label: Label,
+}
+
+/// Predefined label rolls types sold by Brother and defined in the spec
+const LABELS: [LabelEntry; 20] = [
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-22214")),
+		description: Cow::Borrowed("12mm continuous length paper tape"),
+		identifier: Cow::Borrowed("12"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 12,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(12, 0),
+			dots: WidthLength(142, 0),
+			dots_printable: WidthLength(106, 0),
+			right_margin: 29,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-22210")),
+		description: Cow::Borrowed("29mm continuous length paper tape"),
+		identifier: Cow::Borrowed("29"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 29,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(29, 0),
+			dots: WidthLength(342, 0),
+			dots_printable: WidthLength(306, 0),
+			right_margin: 6,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-22225")),
+		description: Cow::Borrowed("38mm continuous length paper tape"),
+		identifier: Cow::Borrowed("38"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 38,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(38, 0),
+			dots: WidthLength(449, 0),
+			dots_printable: WidthLength(413, 0),
+			right_margin: 12,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-22223")),
+		description: Cow::Borrowed("50mm continuous length paper tape"),
+		identifier: Cow::Borrowed("50"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 50,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(50, 0),
+			dots: WidthLength(590, 0),
+			dots_printable: WidthLength(554, 0),
+			right_margin: 12,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-N55224")),
+		description: Cow::Borrowed("54mm continuous length non-adhesive paper tape"),
+		identifier: Cow::Borrowed("54"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 54,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(54, 0),
+			dots: WidthLength(636, 0),
+			dots_printable: WidthLength(590, 0),
+			right_margin: 0,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-22205")),
+		description: Cow::Borrowed("62mm continuous length paper tape"),
+		identifier: Cow::Borrowed("62"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 62,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(62, 0),
+			dots: WidthLength(732, 0),
+			dots_printable: WidthLength(696, 0),
+			right_margin: 12,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-22243")),
+		description: Cow::Borrowed("102mm continuous length paper tape"),
+		identifier: Cow::Borrowed("102"),
+		media_type: MediaType::ContinuousTape,
+		media_width: 102,
+		media_length: 0,
+		label: Label {
+			tape_size: WidthLength(102, 0),
+			dots: WidthLength(1200, 0),
+			dots_printable: WidthLength(1164, 0),
+			right_margin: 12,
+			feed_margin: 35,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11204")),
+		description: Cow::Borrowed("17mm x 54mm multi-purpose labels"),
+		identifier: Cow::Borrowed("17x54"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 17,
+		media_length: 54,
+		label: Label {
+			tape_size: WidthLength(17, 54),
+			dots: WidthLength(201, 636),
+			dots_printable: WidthLength(165, 566),
+			right_margin: 0,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11203")),
+		description: Cow::Borrowed("17mm x 87mm file folder labels"),
+		identifier: Cow::Borrowed("17x87"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 17,
+		media_length: 87,
+		label: Label {
+			tape_size: WidthLength(17, 87),
+			dots: WidthLength(201, 1026),
+			dots_printable: WidthLength(165, 956),
+			right_margin: 0,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11221")),
+		description: Cow::Borrowed("23mm x 23mm square multi-purpose labels"),
+		identifier: Cow::Borrowed("23x23"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 23,
+		media_length: 23,
+		label: Label {
+			tape_size: WidthLength(23, 23),
+			dots: WidthLength(272, 272),
+			dots_printable: WidthLength(202, 202),
+			right_margin: 42,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: None,
+		description: Cow::Borrowed("29mm x 42mm die-cut labels"),
+		identifier: Cow::Borrowed("29x42"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 29,
+		media_length: 42,
+		label: Label {
+			tape_size: WidthLength(29, 42),
+			dots: WidthLength(342, 495),
+			dots_printable: WidthLength(306, 425),
+			right_margin: 6,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11201")),
+		description: Cow::Borrowed("29mm x 90mm standard address labels"),
+		identifier: Cow::Borrowed("29x90"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 29,
+		media_length: 90,
+		label: Label {
+			tape_size: WidthLength(29, 90),
+			dots: WidthLength(342, 1061),
+			dots_printable: WidthLength(306, 991),
+			right_margin: 6,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11208")),
+		description: Cow::Borrowed("38mm x 90mm large address labels"),
+		// The printer reports this roll as 39mm wide but `brother_ql` calls it 38x90
+		identifier: Cow::Borrowed("38x90"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 39,
+		media_length: 90,
+		label: Label {
+			tape_size: WidthLength(38, 90),
+			dots: WidthLength(449, 1061),
+			dots_printable: WidthLength(413, 991),
+			right_margin: 12,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: None,
+		description: Cow::Borrowed("39mm x 48mm die-cut labels"),
+		identifier: Cow::Borrowed("39x48"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 39,
+		media_length: 48,
+		label: Label {
+			tape_size: WidthLength(39, 48),
+			dots: WidthLength(461, 565),
+			dots_printable: WidthLength(425, 495),
+			right_margin: 6,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: None,
+		description: Cow::Borrowed("52mm x 29mm die-cut labels"),
+		identifier: Cow::Borrowed("52x29"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 52,
+		media_length: 29,
+		label: Label {
+			tape_size: WidthLength(52, 29),
+			dots: WidthLength(614, 341),
+			dots_printable: WidthLength(578, 271),
+			right_margin: 0,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11209")),
+		description: Cow::Borrowed("62mm x 29mm small address labels"),
+		identifier: Cow::Borrowed("62x29"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 62,
+		media_length: 29,
+		label: Label {
+			tape_size: WidthLength(62, 29),
+			dots: WidthLength(732, 341),
+			dots_printable: WidthLength(696, 271),
+			right_margin: 12,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11202")),
+		description: Cow::Borrowed("62mm x 100mm shipping labels"),
+		identifier: Cow::Borrowed("62x100"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 62,
+		media_length: 100,
+		label: Label {
+			tape_size: WidthLength(62, 100),
+			dots: WidthLength(732, 1179),
+			dots_printable: WidthLength(696, 1109),
+			right_margin: 12,
+			feed_margin: 0,
+			shape: LabelShape::Rectangle,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11219")),
+		description: Cow::Borrowed("12mm round labels"),
+		identifier: Cow::Borrowed("d12"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 12,
+		media_length: 12,
+		label: Label {
+			tape_size: WidthLength(12, 12),
+			dots: WidthLength(142, 142),
+			dots_printable: WidthLength(94, 94),
+			right_margin: 113,
+			feed_margin: 0,
+			shape: LabelShape::Round,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11218")),
+		description: Cow::Borrowed("24mm round labels"),
+		identifier: Cow::Borrowed("d24"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 24,
+		media_length: 24,
+		label: Label {
+			tape_size: WidthLength(24, 24),
+			dots: WidthLength(284, 284),
+			dots_printable: WidthLength(236, 236),
+			right_margin: 42,
+			feed_margin: 0,
+			shape: LabelShape::Round,
+		},
+	},
+	LabelEntry {
+		part_number: Some(Cow::Borrowed("DK-11207")),
+		description: Cow::Borrowed("58mm round CD/DVD labels"),
+		identifier: Cow::Borrowed("d58"),
+		media_type: MediaType::DieCutLabels,
+		media_width: 58,
+		media_length: 58,
+		label: Label {
+			tape_size: WidthLength(58, 58),
+			dots: WidthLength(688, 688),
+			dots_printable: WidthLength(618, 618),
+			right_margin: 51,
+			feed_margin: 0,
+			shape: LabelShape::Round,
+		},
+	},
+];
+
+/// Label types added at runtime with `register_label()`
+static REGISTERED_LABELS: RwLock<Vec<Arc<LabelEntry>>> = RwLock::new(Vec::new());
+/// `LABELS`, shared the same way as registered label types
+static BUILT_IN_LABELS: OnceLock<Vec<Arc<LabelEntry>>> = OnceLock::new();
+
+/// Add a label type to the catalog at runtime, e.g. for third-party rolls or Brother media this crate doesn't
+/// know about yet.
+///
+/// Registered label types take precedence over built-in ones and over those registered earlier, so registering
+/// media with the same reported dimensions as an existing type overrides it. Registering a label type with the
+/// identifier of one registered before replaces it.
+///
+/// Lookups hand out shared references to the catalog's entries, so a label type that is replaced or unregistered
+/// is freed once the last of them is dropped. Registering an identical label type again keeps the existing entry.
+pub fn register_label(entry: LabelEntry) {
+	let mut registered = REGISTERED_LABELS.write().unwrap_or_else(|err| err.into_inner());
+	let existing = registered.iter().position(|registered| registered.identifier == entry.identifier);
+	let entry = match existing.map(|index| registered.remove(index)) {
+		Some(existing) if *existing == entry => existing,
+		_ => Arc::new(entry),
+	};
+	registered.insert(0, entry);
+}
+
+/// Remove a label type added with `register_label()` from the catalog. Returns whether one was registered with
+/// `identifier`. Built-in label types can't be removed.
+pub fn unregister_label(identifier: &str) -> bool {
+	let mut registered = REGISTERED_LABELS.write().unwrap_or_else(|err| err.into_inner());
+	let count = registered.len();
+	registered.retain(|entry| entry.identifier != identifier);
+	registered.len() != count
+}
+
+/// Iterate over every label type in the catalog, starting with those added with `register_label()`
+pub fn labels() -> impl Iterator<Item = Arc<LabelEntry>> {
+	let registered = REGISTERED_LABELS.read()
+		.unwrap_or_else(|err| err.into_inner())
+		.clone();
+	let built_in = BUILT_IN_LABELS.get_or_init(|| LABELS.iter().cloned().map(Arc::new).collect());
+	registered.into_iter().chain(built_in.iter().cloned())
+}
+
+/// Look up a label type by its Brother part number, e.g. "DK-11201". Case-insensitive.
+pub fn label_by_part_number(part_number: &str) -> Option<Arc<LabelEntry>> {
+	labels().find(|entry| entry.part_number.as_ref().is_some_and(|entry_part_number| entry_part_number.eq_ignore_ascii_case(part_number)))
+}
+
+/// Look up a label type by its short identifier, e.g. "62" or "29x90"
+pub fn label_by_identifier(identifier: &str) -> Option<Arc<LabelEntry>> {
+	labels().find(|entry| entry.identifier == identifier)
+}
+
+/// Look up the label type of the media reported in a printer's status
+pub fn label_for_media(media: &Media) -> Option<Arc<LabelEntry>> {
+	match media.media_type {
+		MediaType::None => None,
+		_ => labels().find(|entry| entry.media_type == media.media_type && entry.media_width == media.width && entry.media_length == media.length),
+	}
 }
 
 /// Returns a corresponding label type given dimensions returned by the printer
 ///
-/// These are predefined label rolls types sold by Brother and defined in the spec
+/// Label types added with `register_label()` are checked first, then the predefined label rolls types sold by
+/// Brother and defined in the spec
 pub fn label_data(width: u8, length: Option<u8>) -> Option<Label> {
-	if let Some(length) = length {
-		// Die cut label
-		match (width, length) {
-			(17, 54) => Some(Label {
-				tape_size: WidthLength(17, 54),
-				dots: WidthLength(201, 636),
-				dots_printable: WidthLength(165, 566),
-				right_margin: 0,
-				feed_margin: 0,
-			}),
-			(17, 87) => Some(Label {
-				tape_size: WidthLength(17, 87),
-				dots: WidthLength(201, 1026),
-				dots_printable: WidthLength(165, 956),
-				right_margin: 0,
-				feed_margin: 0,
-			}),
-			(23, 23) => Some(Label {
-				tape_size: WidthLength(23, 23),
-				dots: WidthLength(272, 272),
-				dots_printable: WidthLength(202, 202),
-				right_margin: 42,
-				feed_margin: 0,
-			}),
-			(29, 42) => Some(Label {
-				tape_size: WidthLength(29, 42),
-				dots: WidthLength(342, 495),
-				dots_printable: WidthLength(306, 425),
-				right_margin: 6,
-				feed_margin: 0,
-			}),
-			(29, 90) => Some(Label {
-				tape_size: WidthLength(29, 90),
-				dots: WidthLength(342, 1061),
-				dots_printable: WidthLength(306, 991),
-				right_margin: 6,
-				feed_margin: 0,
-			}),
-			(39, 90) => Some(Label {
-				tape_size: WidthLength(38, 90),
-				dots: WidthLength(449, 1061),
-				dots_printable: WidthLength(413, 991),
-				right_margin: 12,
-				feed_margin: 0,
-			}),
-			(39, 48) => Some(Label {
-				tape_size: WidthLength(39, 48),
-				dots: WidthLength(461, 565),
-				dots_printable: WidthLength(425, 495),
-				right_margin: 6,
-				feed_margin: 0,
-			}),
-			(52, 29) => Some(Label {
-				tape_size: WidthLength(52, 29),
-				dots: WidthLength(614, 341),
-				dots_printable: WidthLength(578, 271),
-				right_margin: 0,
-				feed_margin: 0,
-			}),
-			(62, 29) => Some(Label {
-				tape_size: WidthLength(62, 29),
-				dots: WidthLength(732, 341),
-				dots_printable: WidthLength(696, 271),
-				right_margin: 12,
-				feed_margin: 0,
-			}),
-			(62, 100) => Some(Label {
-				tape_size: WidthLength(62, 100),
-				dots: WidthLength(732, 1179),
-				dots_printable: WidthLength(696, 1109),
-				right_margin: 12,
-				feed_margin: 0,
-			}),
-			_ => None
+	let length = length.unwrap_or(0);
+	labels()
+		.find(|entry| entry.media_width == width && entry.media_length == length)
+		.map(|entry| entry.label)
+}
+
+#[cfg(feature = "label-files")]
+mod label_files {
+	use std::path::Path;
+	use serde::Deserialize;
+	use crate::error::{ Error, Result };
+	use super::*;
+
+	#[derive(Deserialize)]
+	#[serde(rename_all = "snake_case")]
+	enum MediaTypeDefinition {
+		Continuous,
+		DieCut,
+	}
+
+	#[derive(Deserialize)]
+	#[serde(rename_all = "snake_case")]
+	enum ShapeDefinition {
+		Rectangle,
+		Round,
+	}
+
+	#[derive(Deserialize)]
+	struct LabelDefinition {
+		part_number: Option<String>,
+		description: String,
+		identifier: String,
+		media_type: MediaTypeDefinition,
+		media_width: u8,
+		#[serde(default)]
+		media_length: u8,
+		tape_size: Option<(u32, u32)>,
+		dots: (u32, u32),
+		dots_printable: (u32, u32),
+		right_margin: u8,
+		#[serde(default)]
+		feed_margin: u8,
+		shape: Option<ShapeDefinition>,
+	}
+
+	#[derive(Deserialize)]
+	struct LabelFile {
+		labels: Vec<LabelDefinition>,
+	}
+
+	/// Checks a definition for values that can't describe real media and converts it to a catalog entry
+	fn label_entry(definition: LabelDefinition) -> Result<LabelEntry> {
+		let media_type = match definition.media_type {
+			MediaTypeDefinition::Continuous => MediaType::ContinuousTape,
+			MediaTypeDefinition::DieCut => MediaType::DieCutLabels,
+		};
+		if media_type == MediaType::DieCutLabels && definition.media_length == 0 {
+			return Err(Error::LabelDefinition(format!("Die-cut label type \"{}\" needs a media_length", definition.identifier)));
+		}
+		if definition.dots_printable.0 > definition.dots.0 || definition.dots_printable.1 > definition.dots.1 {
+			return Err(Error::LabelDefinition(format!("The printable area of label type \"{}\" is larger than the label", definition.identifier)));
 		}
+		let tape_size = definition.tape_size.unwrap_or((definition.media_width as u32, definition.media_length as u32));
+		Ok(LabelEntry {
+			part_number: definition.part_number.map(Cow::Owned),
+			description: Cow::Owned(definition.description),
+			identifier: Cow::Owned(definition.identifier),
+			media_type,
+			media_width: definition.media_width,
+			media_length: definition.media_length,
+			label: Label {
+				tape_size: WidthLength(tape_size.0, tape_size.1),
+				dots: WidthLength(definition.dots.0, definition.dots.1),
+				dots_printable: WidthLength(definition.dots_printable.0, definition.dots_printable.1),
+				right_margin: definition.right_margin,
+				feed_margin: definition.feed_margin,
+				shape: match definition.shape {
+					Some(ShapeDefinition::Round) => LabelShape::Round,
+					_ => LabelShape::Rectangle,
+				},
+			},
+		})
 	}
-	else {
-		// Continuous label
-		match width {
-			12 => Some(Label {
-				tape_size: WidthLength(12, 0),
-				dots: WidthLength(142, 0),
-				dots_printable: WidthLength(106, 0),
-				right_margin: 29,
-				feed_margin: 35
-			}),
-			29 => Some(Label {
-				tape_size: WidthLength(29, 0),
-				dots: WidthLength(342, 0),
-				dots_printable: WidthLength(306, 0),
-				right_margin: 6,
-				feed_margin: 35
-			}),
-			38 => Some(Label {
-				tape_size: WidthLength(38, 0),
-				dots: WidthLength(449, 0),
-				dots_printable: WidthLength(413, 0),
-				right_margin: 12,
-				feed_margin: 35
-			}),
-			50 => Some(Label {
-				tape_size: WidthLength(50, 0),
-				dots: WidthLength(590, 0),
-				dots_printable: WidthLength(554, 0),
-				right_margin: 12,
-				feed_margin: 35
-			}),
-			54 => Some(Label {
-				tape_size: WidthLength(54, 0),
-				dots: WidthLength(636, 0),
-				dots_printable: WidthLength(590, 0),
-				right_margin: 0,
-				feed_margin: 35
-			}),
-			62 => Some(Label {
-				tape_size: WidthLength(62, 0),
-				dots: WidthLength(732, 0),
-				dots_printable: WidthLength(696, 0),
-				right_margin: 12,
-				feed_margin: 35
-			}),
-			102 => Some(Label {
-				tape_size: WidthLength(102, 0),
-				dots: WidthLength(1200, 0),
-				dots_printable: WidthLength(1164, 0),
-				right_margin: 12,
-				feed_margin: 35
-			}),
-			_ => None
+
+	/// Registers the label types of a file once all of them are known to be valid
+	fn register_all(file: LabelFile) -> Result<usize> {
+		let entries = file.labels.into_iter().map(label_entry).collect::<Result<Vec<_>>>()?;
+		let count = entries.len();
+		for entry in entries {
+			register_label(entry);
+		}
+		Ok(count)
+	}
+
+	/// Registers every label type defined in a TOML document and returns how many were added
+	///
+	/// Nothing is registered if any definition is invalid, e.g. a die-cut label type without a `media_length` or
+	/// with a printable area larger than the label.
+	///
+	/// Label types are listed in a `labels` array of tables:
+	///
+	/// ```toml
+	/// [[labels]]
+	/// part_number = "DK-11234"     # optional
+	/// description = "60mm x 86mm name badge labels"
+	/// identifier = "60x86"
+	/// media_type = "die_cut"       # or "continuous"
+	/// media_width = 60             # as reported by the printer, in mm
+	/// media_length = 86            # 0 or omitted for continuous tape
+	/// tape_size = [60, 86]         # optional, defaults to the media width and length
+	/// dots = [696, 1014]
+	/// dots_printable = [660, 944]
+	/// right_margin = 12
+	/// feed_margin = 0              # optional
+	/// shape = "rectangle"          # optional, or "round"
+	/// ```
+	pub fn load_labels_toml(document: &str) -> Result<usize> {
+		let file: LabelFile = toml::from_str(document).map_err(|err| Error::LabelDefinition(err.to_string()))?;
+		register_all(file)
+	}
+
+	/// Registers every label type defined in a JSON document and returns how many were added
+	///
+	/// The document is an object with a `labels` array whose entries have the same fields as in `load_labels_toml()`.
+	pub fn load_labels_json(document: &str) -> Result<usize> {
+		let file: LabelFile = serde_json::from_str(document).map_err(|err| Error::LabelDefinition(err.to_string()))?;
+		register_all(file)
+	}
+
+	/// Registers every label type defined in a `.toml` or `.json` file and returns how many were added
+	pub fn load_labels_file<P: AsRef<Path>>(path: P) -> Result<usize> {
+		let path = path.as_ref();
+		let document = std::fs::read_to_string(path)?;
+		match path.extension().and_then(|extension| extension.to_str()) {
+			Some("toml") => load_labels_toml(&document),
+			Some("json") => load_labels_json(&document),
+			_ => Err(Error::LabelDefinition(format!("Unsupported label definition file: {}", path.display()))),
 		}
 	}
 }
+#[cfg(feature = "label-files")]
+pub use label_files::{ load_labels_file, load_labels_json, load_labels_toml };
 
 /// USB Vendor ID for Brother QL printers
 pub const VENDOR_ID: u16 = 0x04F9;
 
+/// Brother QL printer models
+///
+/// Serialized as the model name, e.g. `"QL-820NWB"`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Model {
+	#[cfg_attr(feature = "serde", serde(rename = "QL-500"))]
+	QL500,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-550"))]
+	QL550,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-560"))]
+	QL560,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-570"))]
+	QL570,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-580N"))]
+	QL580N,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-650TD"))]
+	QL650TD,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-700"))]
+	QL700,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-710W"))]
+	QL710W,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-720NW"))]
+	QL720NW,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-800"))]
+	QL800,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-810W"))]
+	QL810W,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-820NWB"))]
+	QL820NWB,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-1050"))]
+	QL1050,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-1060N"))]
+	QL1060N,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-1100"))]
+	QL1100,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-1110NWB"))]
+	QL1110NWB,
+	#[cfg_attr(feature = "serde", serde(rename = "QL-1115NWB"))]
+	QL1115NWB,
+}
+impl Model {
+	/// Get the printer model from a USB Product ID
+	pub fn from_product_id(id: u16) -> Option<Self> {
+		match id {
+			0x2015 => Some(Model::QL500),
+			0x2016 => Some(Model::QL550),
+			0x2027 => Some(Model::QL560),
+			0x2028 => Some(Model::QL570),
+			0x2029 => Some(Model::QL580N),
+			0x201B => Some(Model::QL650TD),
+			0x2042 => Some(Model::QL700),
+			0x2043 => Some(Model::QL710W),
+			0x2044 => Some(Model::QL720NW),
+			0x209B => Some(Model::QL800),
+			0x209C => Some(Model::QL810W),
+			0x209D => Some(Model::QL820NWB),
+			0x2020 => Some(Model::QL1050),
+			0x202A => Some(Model::QL1060N),
+			0x20A7 => Some(Model::QL1100),
+			0x20A8 => Some(Model::QL1110NWB),
+			0x20AB => Some(Model::QL1115NWB),
+			_ => None
+		}
+	}
+
+	/// Get the printer model from the model code in a status message. The QL-500 and QL-550 report the same
+	/// code and are both returned as `Model::QL500`.
+	pub fn from_status_code(code: u8) -> Option<Self> {
+		match code {
+			0x4F => Some(Model::QL500),
+			0x31 => Some(Model::QL560),
+			0x32 => Some(Model::QL570),
+			0x33 => Some(Model::QL580N),
+			0x51 => Some(Model::QL650TD),
+			0x35 => Some(Model::QL700),
+			0x36 => Some(Model::QL710W),
+			0x37 => Some(Model::QL720NW),
+			0x38 => Some(Model::QL800),
+			0x39 => Some(Model::QL810W),
+			0x41 => Some(Model::QL820NWB),
+			0x50 => Some(Model::QL1050),
+			0x34 => Some(Model::QL1060N),
+			0x43 => Some(Model::QL1100),
+			0x44 => Some(Model::QL1110NWB),
+			0x45 => Some(Model::QL1115NWB),
+			_ => None
+		}
+	}
+
+	/// The model name as printed on the printer
+	pub fn name(&self) -> &'static str {
+		match self {
+			Model::QL500 => "QL-500",
+			Model::QL550 => "QL-550",
+			Model::QL560 => "QL-560",
+			Model::QL570 => "QL-570",
+			Model::QL580N => "QL-580N",
+			Model::QL650TD => "QL-650TD",
+			Model::QL700 => "QL-700",
+			Model::QL710W => "QL-710W",
+			Model::QL720NW => "QL-720NW",
+			Model::QL800 => "QL-800",
+			Model::QL810W => "QL-810W",
+			Model::QL820NWB => "QL-820NWB",
+			Model::QL1050 => "QL-1050",
+			Model::QL1060N => "QL-1060N",
+			Model::QL1100 => "QL-1100",
+			Model::QL1110NWB => "QL-1110NWB",
+			Model::QL1115NWB => "QL-1115NWB",
+		}
+	}
+
+	/// The print head of this model
+	pub fn print_head(&self) -> PrintHead {
+		match self {
+			Model::QL1050 | Model::QL1060N |
+			Model::QL1100 | Model::QL1110NWB | Model::QL1115NWB => PrintHead::Wide,
+			_ => PrintHead::Standard
+		}
+	}
+
+	/// Whether the printer accepts the printer settings commands (`ESC i U`), e.g. for auto power-off and
+	/// auto power-on. Only the QL-800 and QL-1100 series implement them.
+	pub fn supports_settings(&self) -> bool {
+		matches!(self,
+			Model::QL800 | Model::QL810W | Model::QL820NWB |
+			Model::QL1100 | Model::QL1110NWB | Model::QL1115NWB
+		)
+	}
+
+	/// Whether the printer can fill in and print templates stored on it (P-touch Template mode)
+	pub fn supports_template_mode(&self) -> bool {
+		matches!(self,
+			Model::QL580N | Model::QL720NW | Model::QL1060N |
+			Model::QL800 | Model::QL810W | Model::QL820NWB |
+			Model::QL1100 | Model::QL1110NWB | Model::QL1115NWB
+		)
+	}
+}
+
 /// Get the string representation of a printer's model name from a USB Product ID
 pub fn printer_name_from_id(id: u16) -> Option<&'static str> {
-	match id {
-		0x2015 => Some("QL-500"),
-		0x2016 => Some("QL-550"),
-		0x2027 => Some("QL-560"),
-		0x2028 => Some("QL-570"),
-		0x2029 => Some("QL-580N"),
-		0x201B => Some("QL-650TD"),
-		0x2042 => Some("QL-700"),
-		0x2020 => Some("QL-1050"),
-		0x202A => Some("QL-1060N"),
-		_ => None
+	Model::from_product_id(id).map(|model| model.name())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn label_catalog() {
+		let address = label_by_part_number("dk-11201").unwrap();
+		assert_eq!(address.identifier, "29x90");
+		assert_eq!(label_by_identifier("29x90"), Some(address.clone()));
+
+		let media = Media { media_type: MediaType::ContinuousTape, width: 62, length: 0 };
+		assert_eq!(label_for_media(&media).unwrap().part_number.as_deref(), Some("DK-22205"));
+		assert_eq!(label_data(62, None), Some(label_by_identifier("62").unwrap().label));
+		assert!(labels().all(|entry| label_by_identifier(&entry.identifier) == Some(entry)));
+
+		let large_address = label_by_identifier("38x90").unwrap();
+		assert_eq!(large_address.part_number.as_deref(), Some("DK-11208"));
+		assert_eq!(large_address.label.tape_size, WidthLength(38, 90));
+		assert!(label_by_identifier("39x90").is_none());
+		let media = Media { media_type: MediaType::DieCutLabels, width: 39, length: 90 };
+		assert_eq!(label_for_media(&media), Some(large_address));
+	}
+
+	#[test]
+	fn custom_labels() {
+		/// Removes the test's label types from the global catalog even if an assertion fails
+		struct Registered(&'static [&'static str]);
+		impl Drop for Registered {
+			fn drop(&mut self) {
+				for identifier in self.0 {
+					unregister_label(identifier);
+				}
+			}
+		}
+		let _registered = Registered(&["61x100", "60x86"]);
+		let count = |identifier| labels().filter(|entry| entry.identifier == identifier).count();
+
+		let mut entry = LabelEntry::clone(&label_by_identifier("62x100").unwrap());
+		entry.identifier = "61x100".into();
+		entry.part_number = None;
+		entry.media_width = 61;
+		register_label(entry.clone());
+		assert_eq!(label_data(61, Some(100)), Some(entry.label));
+		let registered = label_by_identifier("61x100").unwrap();
+		assert_eq!(*registered, entry);
+
+		// Registering the same label type again reuses the entry, and a changed one replaces it
+		register_label(entry.clone());
+		assert!(Arc::ptr_eq(&label_by_identifier("61x100").unwrap(), &registered));
+		assert_eq!(count("61x100"), 1);
+		entry.description = String::from("61mm x 100mm labels").into();
+		register_label(entry);
+		assert_eq!(label_by_identifier("61x100").unwrap().description, "61mm x 100mm labels");
+		assert_eq!(count("61x100"), 1);
+		// The replaced entry isn't kept alive by the catalog
+		assert_eq!(Arc::strong_count(&registered), 1);
+
+		let replaced = label_by_identifier("61x100").unwrap();
+		assert!(unregister_label("61x100"));
+		assert!(!unregister_label("61x100"));
+		assert_eq!(label_data(61, Some(100)), None);
+		assert_eq!(Arc::strong_count(&replaced), 1);
+		assert!(!unregister_label("62x100"));
+		assert!(label_by_identifier("62x100").is_some());
+
+		#[cfg(feature = "label-files")]
+		{
+			let document = r#"
+				[[labels]]
+				description = "60mm x 86mm name badge labels"
+				identifier = "60x86"
+				media_type = "die_cut"
+				media_width = 60
+				media_length = 86
+				dots = [696, 1014]
+				dots_printable = [660, 944]
+				right_margin = 12
+			"#;
+			let added = load_labels_toml(document).unwrap();
+			assert_eq!(added, 1);
+			let label = label_data(60, Some(86)).unwrap();
+			assert_eq!(label.tape_size, WidthLength(60, 86));
+			assert_eq!(label.dots_printable, WidthLength(660, 944));
+
+			let registered = label_by_identifier("60x86").unwrap();
+			load_labels_toml(document).unwrap();
+			assert!(Arc::ptr_eq(&label_by_identifier("60x86").unwrap(), &registered));
+			assert_eq!(count("60x86"), 1);
+		}
+	}
+
+	#[cfg(feature = "label-files")]
+	#[test]
+	fn invalid_label_files() {
+		use crate::error::Error;
+
+		let definition = |media_length: u8, dots_printable: &str| format!(r#"
+			[[labels]]
+			description = "Invalid labels"
+			identifier = "invalid"
+			media_type = "die_cut"
+			media_width = 60
+			media_length = {}
+			dots = [696, 1014]
+			dots_printable = {}
+			right_margin = 12
+		"#, media_length, dots_printable);
+
+		// Die-cut labels without a length would be mistaken for continuous tape
+		assert!(matches!(load_labels_toml(&definition(0, "[660, 944]")), Err(Error::LabelDefinition(_))));
+		assert!(matches!(load_labels_toml(&definition(86, "[700, 944]")), Err(Error::LabelDefinition(_))));
+		assert!(matches!(load_labels_toml(&definition(86, "[660, 1020]")), Err(Error::LabelDefinition(_))));
+		assert!(label_by_identifier("invalid").is_none());
 	}
 }
@@ -0,0 +1,208 @@
+//! Conversions between physical units and printer dots, and the position of a label's printable area
+//!
+//! The print head always has a resolution of 300 dpi across the tape. In the feed direction (along the tape) the
+//! resolution is 300 dpi as well, or 600 dpi when high resolution printing is enabled.
+
+use super::constants::{ Label, WidthLength };
+
+const MM_PER_INCH: f32 = 25.4;
+const POINTS_PER_INCH: f32 = 72.0;
+
+/// Print resolution, selected with `PrintOptions::high_resolution`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resolution {
+	/// 300 x 300 dpi
+	Standard,
+	/// 300 dpi across the tape and 600 dpi in the feed direction
+	High,
+}
+
+/// A direction on the label
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Axis {
+	/// Across the tape, along the print head's pins
+	Width,
+	/// Along the tape, in the feed direction
+	Length,
+}
+
+impl Resolution {
+	/// Dots per inch along the given axis
+	pub fn dpi(self, axis: Axis) -> u32 {
+		match (self, axis) {
+			(Resolution::High, Axis::Length) => 600,
+			_ => 300,
+		}
+	}
+}
+
+/// Convert a length in inches to dots along an axis
+pub fn inches_to_dots(inches: f32, axis: Axis, resolution: Resolution) -> u32 {
+	(inches * resolution.dpi(axis) as f32).round().max(0.0) as u32
+}
+
+/// Convert a length in millimeters to dots along an axis
+pub fn mm_to_dots(mm: f32, axis: Axis, resolution: Resolution) -> u32 {
+	inches_to_dots(mm / MM_PER_INCH, axis, resolution)
+}
+
+/// Convert a length in typographic points (1/72 inch) to dots along an axis
+pub fn pt_to_dots(points: f32, axis: Axis, resolution: Resolution) -> u32 {
+	inches_to_dots(points / POINTS_PER_INCH, axis, resolution)
+}
+
+/// Convert a number of dots along an axis to millimeters
+pub fn dots_to_mm(dots: u32, axis: Axis, resolution: Resolution) -> f32 {
+	dots as f32 * MM_PER_INCH / resolution.dpi(axis) as f32
+}
+
+/// A rectangle measured in dots. `x` is across the tape and `y` along it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Label {
+	/// Size of the whole label in dots at the given resolution. The length is `0` for continuous tape.
+	pub fn dots_at(&self, resolution: Resolution) -> WidthLength {
+		WidthLength(self.dots.0, self.dots.1 * resolution.dpi(Axis::Length) / 300)
+	}
+
+	/// Size of the printable area in dots at the given resolution. The length is `0` for continuous tape.
+	pub fn dots_printable_at(&self, resolution: Resolution) -> WidthLength {
+		WidthLength(self.dots_printable.0, self.dots_printable.1 * resolution.dpi(Axis::Length) / 300)
+	}
+
+	/// The printable area relative to the label's top left corner, in dots. For continuous tape the height is `0`
+	/// since the length is whatever is printed.
+	pub fn printable_rect(&self, resolution: Resolution) -> Rect {
+		let dots = self.dots_at(resolution);
+		let printable = self.dots_printable_at(resolution);
+		Rect {
+			x: dots.0.saturating_sub(printable.0) / 2,
+			y: dots.1.saturating_sub(printable.1) / 2,
+			width: printable.0,
+			height: printable.1,
+		}
+	}
+
+	/// Index of the first pin of `head` that covers the printable area, counted from the start of a raster line
+	pub fn head_offset(&self, head: PrintHead) -> u32 {
+		self.right_margin as u32 + head.label_offset()
+	}
+
+	/// Whether the printable area lies entirely within the pins of `head`
+	pub fn fits_head(&self, head: PrintHead) -> bool {
+		self.head_offset(head) + self.dots_printable.0 <= head.pins()
+	}
+}
+
+/// The thermal print head of a printer model, which determines the length of raster lines and where on them
+/// each label's printable area lies
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PrintHead {
+	/// The 720 pin print head of the QL-500, QL-700, QL-800 and similar models, up to 62mm labels
+	Standard,
+	/// The 1296 pin print head of the QL-1050, QL-1060N and QL-1100 series, up to 102mm labels
+	Wide,
+}
+impl PrintHead {
+	/// Number of pins, i.e. dots across the tape
+	pub fn pins(self) -> u32 {
+		match self {
+			PrintHead::Standard => 720,
+			PrintHead::Wide => 1296,
+		}
+	}
+
+	/// Number of bytes in each raster line sent to a printer with this head
+	pub fn bytes_per_line(self) -> usize {
+		self.pins() as usize / 8
+	}
+
+	/// Label margins are given for the standard head. Tape is centered differently on the wide head's pins.
+	fn label_offset(self) -> u32 {
+		match self {
+			PrintHead::Standard => 0,
+			PrintHead::Wide => 44,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::printer::{ constants, job, status };
+	use crate::printer::options::PrintOptions;
+	use crate::printer::status::PrinterError;
+	use crate::printer::tests::status_with;
+	use crate::raster::ImageRasterizer;
+
+	#[test]
+	fn label_geometry() {
+		assert_eq!(inches_to_dots(1.0, Axis::Width, Resolution::High), 300);
+		assert_eq!(mm_to_dots(25.4, Axis::Length, Resolution::High), 600);
+		assert_eq!(pt_to_dots(72.0, Axis::Length, Resolution::Standard), 300);
+		assert_eq!(mm_to_dots(5.0, Axis::Width, Resolution::Standard), 59);
+
+		let label = constants::label_data(62, Some(100)).unwrap();
+		assert_eq!(label.printable_rect(Resolution::Standard), Rect { x: 18, y: 35, width: 696, height: 1109 });
+		assert_eq!(label.printable_rect(Resolution::High), Rect { x: 18, y: 70, width: 696, height: 2218 });
+	}
+
+	#[test]
+	fn wide_head() {
+		assert_eq!(PrintHead::Wide.pins(), 1296);
+		assert_eq!(PrintHead::Wide.bytes_per_line(), 162);
+		assert_eq!(constants::Model::QL1100.print_head(), PrintHead::Wide);
+		assert_eq!(constants::Model::QL820NWB.print_head(), PrintHead::Standard);
+
+		// Tape sits 44 pins further in on the wide head, so 102mm tape starts at pin 12 + 44
+		let wide_tape = constants::label_by_identifier("102").unwrap().label;
+		assert_eq!(wide_tape.head_offset(PrintHead::Standard), 12);
+		assert_eq!(wide_tape.head_offset(PrintHead::Wide), 56);
+		assert!(wide_tape.fits_head(PrintHead::Wide));
+		assert!(!wide_tape.fits_head(PrintHead::Standard));
+
+		// A black image fills pins 56 through 1219
+		let mut rasterizer = ImageRasterizer::new(wide_tape);
+		rasterizer.set_print_head(PrintHead::Wide);
+		let lines = rasterizer.rasterize(&image::DynamicImage::ImageLuma8(image::GrayImage::new(200, 100))).unwrap();
+		assert!(lines.iter().all(|line| line.len() == 162));
+		assert!(lines.iter().all(|line| line[..7].iter().all(|&byte| byte == 0) && line[7] == 0xFF && line[152] == 0xF0 && line[153..].iter().all(|&byte| byte == 0)));
+		assert!(lines.iter().all(|line| line.iter().map(|byte| byte.count_ones()).sum::<u32>() == 1164));
+
+		let status = status_with(PrinterError::empty(), status::MediaType::ContinuousTape, 102, 0);
+		assert!(job::check_status(&status, &[&lines], Some(&wide_tape), PrintHead::Wide, &PrintOptions::default()).is_ok());
+	}
+
+	#[test]
+	fn round_labels() {
+		let round = constants::label_by_identifier("d24").unwrap().label;
+		let rasterizer = ImageRasterizer::new(round);
+		let black = image::DynamicImage::ImageLuma8(image::GrayImage::new(300, 300));
+		let lines = rasterizer.rasterize(&black).unwrap();
+		assert_eq!(lines.len(), 236);
+
+		// Only the circle inscribed in the square printable area is inked
+		let offset = round.head_offset(PrintHead::Standard);
+		let radius = round.dots_printable.0 as f32 / 2.0;
+		let mut inked = 0;
+		for (x, line) in lines.iter().enumerate() {
+			for pin in (0..line.len() as u32 * 8).filter(|pin| line[*pin as usize / 8] & (0x80 >> (pin % 8)) != 0) {
+				let dx = (x as f32 + 0.5 - radius) / radius;
+				let dy = ((pin - offset) as f32 + 0.5 - radius) / radius;
+				assert!(dx * dx + dy * dy <= 1.0, "Pin {} of line {} is outside of the circle", pin, x);
+				inked += 1;
+			}
+		}
+		let circle = std::f32::consts::PI * radius * radius;
+		assert!((inked as f32 - circle).abs() < circle * 0.01);
+		// The middle line is inked all the way across, the first one only around its center
+		assert_eq!(lines[118].iter().map(|byte| byte.count_ones()).sum::<u32>(), 236);
+		assert!(lines[0].iter().map(|byte| byte.count_ones()).sum::<u32>() < 30);
+	}
+}
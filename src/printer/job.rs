@@ -0,0 +1,374 @@
+//! Print jobs and the checks run before a job is sent to the printer
+
+use std::ops::Range;
+use std::thread;
+use std::time::{ Duration, Instant };
+use super::constants::{ label_data, Label };
+use super::geometry::PrintHead;
+use super::options::PrintOptions;
+use super::{ status, Cancellation, Error, Progress, RasterLine, Result, ThermalPrinter };
+
+/// One or more pages of raster lines together with the label media they were rendered for
+///
+/// When serialized, each raster line is an array of bytes and `recovery_timeout` is `null` or an object with
+/// `secs` and `nanos`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrintJob {
+	/// The label media the pages were rendered for
+	pub label: Label,
+	/// Raster lines of each page. Each die-cut label or length of continuous tape is one page.
+	pub pages: Vec<Vec<RasterLine>>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub options: PrintOptions,
+	/// Opt-in recovery from errors a person can fix, such as an open cover or an empty roll. When set,
+	/// `print_job_blocking()` waits up to this long for the error to be cleared and the same label type to be
+	/// loaded, resets the printer, then re-sends the interrupted page and continues with the rest of the job.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub recovery_timeout: Option<Duration>,
+}
+impl PrintJob {
+	/// Creates a job with the default `PrintOptions`
+	pub fn new(label: Label, pages: Vec<Vec<RasterLine>>) -> Self {
+		Self {
+			label,
+			pages,
+			options: PrintOptions::default(),
+			recovery_timeout: None,
+		}
+	}
+}
+
+/// Checks that a status allows printing `pages` with `options` on a printer with the given print head, optionally
+/// rendered for `rendered_for`, and returns the loaded label
+pub(crate) fn check_status<P: AsRef<[RasterLine]>>(status: &status::Response, pages: &[P], rendered_for: Option<&Label>, head: PrintHead, options: &PrintOptions) -> Result<Label> {
+	if !status.errors.is_empty() {
+		return Err(Error::Printer(status.errors));
+	}
+	if let status::MediaType::None = status.media.media_type {
+		return Err(Error::NoMedia);
+	}
+	let loaded = label_data(status.media.width, match status.media.length {
+		0 => None,
+		length => Some(length),
+	}).ok_or(Error::UnknownMedia { width: status.media.width, length: status.media.length })?;
+
+	if !loaded.fits_head(head) {
+		return Err(Error::Unsupported("The loaded media is wider than this printer's print head"));
+	}
+	let line_length = head.bytes_per_line();
+	if let Some(line) = pages.iter().flat_map(|page| page.as_ref()).find(|line| line.len() != line_length) {
+		return Err(Error::RasterLineLength { expected: line_length, found: line.len() });
+	}
+
+	if let Some(rendered_for) = rendered_for {
+		if rendered_for.dots.0 != loaded.dots.0 {
+			return Err(Error::LabelWidthMismatch { rendered_for: rendered_for.dots.0, loaded: loaded.dots.0 });
+		}
+	}
+	if options.check_printable_area {
+		let start = loaded.head_offset(head);
+		let printable = start..start + loaded.dots_printable.0;
+		if let Some(pin) = pages.iter().flat_map(|page| page.as_ref()).find_map(|line| ink_outside(line, &printable)) {
+			return Err(Error::RasterOutOfBounds { pin });
+		}
+	}
+	if loaded.tape_size.1 != 0 {
+		// Die-cut labels can't fit more than their printable length, which has twice as many lines at high resolution
+		let max = loaded.dots_printable_at(options.resolution()).1;
+		if let Some(page) = pages.iter().find(|page| page.as_ref().len() > max as usize) {
+			return Err(Error::RasterTooLong { lines: page.as_ref().len(), max });
+		}
+	}
+	if let Some(rendered_for) = rendered_for {
+		if rendered_for.tape_size != loaded.tape_size {
+			return Err(Error::MediaMismatch { expected: rendered_for.tape_size, loaded: loaded.tape_size });
+		}
+	}
+	Ok(loaded)
+}
+
+/// The first pin outside of `printable` that `line` prints on
+fn ink_outside(line: &[u8], printable: &Range<u32>) -> Option<u32> {
+	line.iter()
+		.enumerate()
+		.filter(|(_, &byte)| byte != 0)
+		.flat_map(|(index, &byte)| (0..8).filter(move |bit| byte & (0x80 >> bit) != 0).map(move |bit| index as u32 * 8 + bit))
+		.find(|pin| !printable.contains(pin))
+}
+
+/// The errors a person can fix that caused `err`, if it can be recovered from
+fn recoverable_errors(err: &Error) -> Option<status::PrinterError> {
+	match err {
+		Error::Printer(errors) if errors.is_recoverable() => Some(*errors),
+		Error::NoMedia => Some(status::PrinterError::NO_MEDIA),
+		_ => None,
+	}
+}
+
+/// Prints `pages` pages one at a time with `print_page`, which returns once the page has been printed. Recoverable
+/// errors are waited out with `recover`, after which the failed page is printed again.
+fn print_pages_with_recovery<P, R, C, F>(pages: usize, mut print_page: P, mut recover: R, cancelled: C, mut progress: F) -> Result<()>
+where
+	P: FnMut(usize, &mut F) -> Result<()>,
+	R: FnMut() -> Result<()>,
+	C: Fn() -> bool,
+	F: FnMut(Progress),
+{
+	for page in 0..pages {
+		loop {
+			if cancelled() {
+				return Err(Error::Cancelled);
+			}
+			let err = match print_page(page, &mut progress) {
+				Ok(()) => break,
+				Err(err) => err,
+			};
+			let errors = recoverable_errors(&err).ok_or(err)?;
+			progress(Progress::Recovering { page, errors });
+			recover()?;
+			progress(Progress::Resumed { page });
+		}
+		progress(Progress::PageCompleted { completed: page + 1, total: pages });
+	}
+	Ok(())
+}
+
+impl<T: rusb::UsbContext> ThermalPrinter<T> {
+	/// Checks that the printer is ready to print `job` without sending anything that would start printing.
+	///
+	/// Fails if the printer reports any error (e.g. the cover is open), if the job was rendered for a label with
+	/// a different width or size than the one that is loaded, if a page prints outside of the loaded label's
+	/// printable area, or if a page is longer than a loaded die-cut label. Returns the printer's current status
+	/// otherwise.
+	pub fn preflight(&self, job: &PrintJob) -> Result<status::Response> {
+		let status = self.get_status()?;
+		check_status(&status, &job.pages, Some(&job.label), self.print_head(), &job.options)?;
+		Ok(status)
+	}
+
+	/// Sends all pages of a job to the printer after running the `preflight()` checks, begins printing, and
+	/// immediately returns
+	pub fn print_job(&self, job: &PrintJob) -> Result<status::Response> {
+		let cancellation = self.cancellation(None);
+		let _sending = self.lock_sending();
+		self.print_job_unlocked(job, &cancellation)
+	}
+
+	fn print_job_unlocked(&self, job: &PrintJob, cancellation: &Cancellation<'_>) -> Result<status::Response> {
+		if job.pages.is_empty() {
+			return Err(Error::InvalidArgument("Print job has no pages"));
+		}
+		let status = self.get_status()?;
+		let label = check_status(&status, &job.pages, Some(&job.label), self.print_head(), &job.options)?;
+		for (index, page) in job.pages.iter().enumerate() {
+			self.send_page(page, &status.media, &label, &job.options, index, index + 1 == job.pages.len(), cancellation)?;
+		}
+		self.read()
+	}
+
+	/// Same as `print_job()` but will not return until the printer reports that it has finished printing every page.
+	///
+	/// If the job has a `recovery_timeout`, pages are sent one at a time and recoverable printer errors are waited
+	/// out instead of failing the job. Loading a different label type than the job was rendered for while
+	/// recovering aborts the job with `Error::MediaMismatch`. Recovering resets the printer, which discards
+	/// whatever it had received of the interrupted page.
+	pub fn print_job_blocking(&self, job: &PrintJob) -> Result<()> {
+		self.print_job_blocking_with_progress(job, |_| {})
+	}
+
+	/// Same as `print_job_blocking()` but calls `progress` for every phase change and completed page, and when
+	/// the job starts and finishes recovering from an error. `progress` may call `cancel()` to stop the job.
+	pub fn print_job_blocking_with_progress<F: FnMut(Progress)>(&self, job: &PrintJob, progress: F) -> Result<()> {
+		let cancellation = self.cancellation(None);
+		let _sending = self.lock_sending();
+		self.with_notifications(|| self.print_job_and_wait(job, &cancellation, progress))
+	}
+
+	fn print_job_and_wait<F: FnMut(Progress)>(&self, job: &PrintJob, cancellation: &Cancellation<'_>, progress: F) -> Result<()> {
+		let recovery_timeout = match job.recovery_timeout {
+			Some(recovery_timeout) => recovery_timeout,
+			None => {
+				self.print_job_unlocked(job, cancellation)?;
+				return self.wait_for_pages(job.pages.len(), super::PRINT_TIMEOUT * job.pages.len() as u32, cancellation, progress);
+			},
+		};
+		if job.pages.is_empty() {
+			return Err(Error::InvalidArgument("Print job has no pages"));
+		}
+
+		print_pages_with_recovery(
+			job.pages.len(),
+			|index, progress| {
+				self.print_job_page(job, &job.pages[index], index, cancellation)?;
+				// Completed pages are counted across the whole job instead
+				self.wait_for_pages(1, super::PRINT_TIMEOUT, cancellation, |event| {
+					if let Progress::PhaseChanged(_) = event {
+						progress(event);
+					}
+				})
+			},
+			|| self.wait_for_recovery(&job.label, recovery_timeout, cancellation),
+			|| cancellation.is_cancelled(),
+			progress,
+		)
+	}
+
+	fn print_job_page(&self, job: &PrintJob, page: &[RasterLine], index: usize, cancellation: &Cancellation<'_>) -> Result<()> {
+		let status = self.get_status()?;
+		let label = check_status(&status, &[page], Some(&job.label), self.print_head(), &job.options)?;
+		self.send_page(page, &status.media, &label, &job.options, index, true, cancellation)
+	}
+
+	/// Polls the printer until recoverable errors are cleared and media matching `label` is loaded, then resets it
+	/// to discard the partially received page
+	fn wait_for_recovery(&self, label: &Label, timeout: Duration, cancellation: &Cancellation<'_>) -> Result<()> {
+		const POLL_INTERVAL: Duration = Duration::from_millis(500);
+		// Sleep in short slices between polls so that cancellation is noticed promptly
+		const CANCEL_INTERVAL: Duration = Duration::from_millis(50);
+
+		let deadline = Instant::now() + timeout;
+		loop {
+			let status = self.get_status()?;
+			match check_status::<&[RasterLine]>(&status, &[], Some(label), self.print_head(), &PrintOptions::default()) {
+				Ok(_) => break,
+				Err(Error::Printer(errors)) if errors.is_recoverable() => {},
+				Err(Error::NoMedia) => {},
+				Err(err) => return Err(err),
+			}
+			let poll = Instant::now() + POLL_INTERVAL;
+			loop {
+				if cancellation.is_cancelled() {
+					return Err(Error::Cancelled);
+				}
+				let now = Instant::now();
+				if now >= deadline {
+					return Err(Error::Timeout);
+				}
+				if now >= poll {
+					break;
+				}
+				thread::sleep(CANCEL_INTERVAL.min(poll - now));
+			}
+		}
+		self.reset_unlocked()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+	use crate::printer::status::PrinterError;
+	use crate::printer::tests::status_with;
+
+	#[test]
+	fn recoverable() {
+		assert_eq!(recoverable_errors(&Error::Printer(PrinterError::COVER_OPEN)), Some(PrinterError::COVER_OPEN));
+		assert_eq!(recoverable_errors(&Error::Printer(PrinterError::END_OF_MEDIA | PrinterError::CANNOT_FEED)), Some(PrinterError::END_OF_MEDIA | PrinterError::CANNOT_FEED));
+		assert_eq!(recoverable_errors(&Error::NoMedia), Some(PrinterError::NO_MEDIA));
+		// A single fatal error makes the whole set unrecoverable
+		assert_eq!(recoverable_errors(&Error::Printer(PrinterError::COVER_OPEN | PrinterError::CUTTER_JAM)), None);
+		assert_eq!(recoverable_errors(&Error::Printer(PrinterError::SYSTEM_ERROR)), None);
+		assert_eq!(recoverable_errors(&Error::Printer(PrinterError::empty())), None);
+		assert_eq!(recoverable_errors(&Error::Timeout), None);
+		assert_eq!(recoverable_errors(&Error::UnknownMedia { width: 40, length: 0 }), None);
+	}
+
+	/// Runs `print_pages_with_recovery()` over 3 pages where `fail` decides whether the nth attempt fails
+	fn print_pages<E: Fn(usize) -> Option<Error>>(fail: E) -> (Result<()>, Vec<usize>, Vec<Progress>) {
+		let attempts = Cell::new(0);
+		let mut printed = Vec::new();
+		let mut progress = Vec::new();
+		let result = print_pages_with_recovery(3, |page, _| {
+			let attempt = attempts.get();
+			attempts.set(attempt + 1);
+			printed.push(page);
+			fail(attempt).map_or(Ok(()), Err)
+		}, || Ok(()), || false, |event| progress.push(event));
+		(result, printed, progress)
+	}
+
+	#[test]
+	fn recovery() {
+		// The second page fails and is printed again after recovering
+		let (result, printed, progress) = print_pages(|attempt| if attempt == 1 { Some(Error::Printer(PrinterError::END_OF_MEDIA)) } else { None });
+		assert!(result.is_ok());
+		assert_eq!(printed, [0, 1, 1, 2]);
+		assert_eq!(progress, [
+			Progress::PageCompleted { completed: 1, total: 3 },
+			Progress::Recovering { page: 1, errors: PrinterError::END_OF_MEDIA },
+			Progress::Resumed { page: 1 },
+			Progress::PageCompleted { completed: 2, total: 3 },
+			Progress::PageCompleted { completed: 3, total: 3 },
+		]);
+
+		let (result, printed, _) = print_pages(|attempt| if attempt == 0 { Some(Error::NoMedia) } else { None });
+		assert!(result.is_ok());
+		assert_eq!(printed, [0, 0, 1, 2]);
+
+		// Fatal errors abort the job without recovering
+		let (result, printed, progress) = print_pages(|attempt| if attempt == 1 { Some(Error::Printer(PrinterError::CUTTER_JAM)) } else { None });
+		assert!(matches!(result, Err(Error::Printer(PrinterError::CUTTER_JAM))));
+		assert_eq!(printed, [0, 1]);
+		assert_eq!(progress, [Progress::PageCompleted { completed: 1, total: 3 }]);
+
+		// Failing to recover ends the job
+		let result = print_pages_with_recovery(2, |_, _| Err(Error::Printer(PrinterError::COVER_OPEN)), || Err(Error::Timeout), || false, |_| {});
+		assert!(matches!(result, Err(Error::Timeout)));
+
+		// Cancelling stops before the next attempt
+		let cancelled = Cell::new(false);
+		let mut printed = Vec::new();
+		let result = print_pages_with_recovery(3, |page, _| {
+			printed.push(page);
+			cancelled.set(true);
+			Err(Error::Printer(PrinterError::COVER_OPEN))
+		}, || Ok(()), || cancelled.get(), |_| {});
+		assert!(matches!(result, Err(Error::Cancelled)));
+		assert_eq!(printed, [0]);
+	}
+
+	#[test]
+	fn preflight() {
+		let die_cut = label_data(29, Some(90)).unwrap();
+		let pages = vec![vec![vec![0; 90]; die_cut.dots_printable.1 as usize]];
+		let defaults = PrintOptions::default();
+
+		let ready = status_with(PrinterError::empty(), status::MediaType::DieCutLabels, 29, 90);
+		assert_eq!(check_status(&ready, &pages, Some(&die_cut), PrintHead::Standard, &defaults).unwrap(), die_cut);
+
+		let cover_open = status_with(PrinterError::COVER_OPEN, status::MediaType::DieCutLabels, 29, 90);
+		assert!(matches!(check_status(&cover_open, &pages, None, PrintHead::Standard, &defaults), Err(Error::Printer(PrinterError::COVER_OPEN))));
+
+		let too_long = vec![vec![0; 90]; die_cut.dots_printable.1 as usize + 1];
+		assert!(matches!(check_status(&ready, &[too_long], None, PrintHead::Standard, &defaults), Err(Error::RasterTooLong { .. })));
+
+		// High resolution doubles the number of lines that fit on a die-cut label
+		let high_res = PrintOptions { high_resolution: true, ..PrintOptions::default() };
+		let double_length = vec![vec![0; 90]; die_cut.dots_printable.1 as usize * 2];
+		assert!(check_status(&ready, &[&double_length], None, PrintHead::Standard, &high_res).is_ok());
+		assert!(matches!(check_status(&ready, &[&double_length], None, PrintHead::Standard, &defaults), Err(Error::RasterTooLong { .. })));
+
+		// The 29mm label prints on pins 6 through 311
+		let mut line = vec![0; 90];
+		line[0] = 0x02;
+		assert!(check_status(&ready, &[vec![line.clone()]], None, PrintHead::Standard, &defaults).is_ok());
+		line[0] = 0x80;
+		assert!(matches!(check_status(&ready, &[vec![line.clone()]], None, PrintHead::Standard, &defaults), Err(Error::RasterOutOfBounds { pin: 0 })));
+		let unchecked = PrintOptions { check_printable_area: false, ..PrintOptions::default() };
+		assert!(check_status(&ready, &[vec![line]], None, PrintHead::Standard, &unchecked).is_ok());
+		let mut line = vec![0; 90];
+		line[39] = 0x80;
+		assert!(matches!(check_status(&ready, &[vec![line]], None, PrintHead::Standard, &defaults), Err(Error::RasterOutOfBounds { pin: 312 })));
+
+		let wide = label_data(62, None).unwrap();
+		assert!(matches!(check_status(&ready, &pages, Some(&wide), PrintHead::Standard, &defaults), Err(Error::LabelWidthMismatch { rendered_for: 732, loaded: 342 })));
+
+		let continuous = label_data(29, None).unwrap();
+		assert!(matches!(check_status(&ready, &pages, Some(&continuous), PrintHead::Standard, &defaults), Err(Error::MediaMismatch { .. })));
+
+		assert!(matches!(check_status(&ready, &pages, None, PrintHead::Wide, &defaults), Err(Error::RasterLineLength { expected: 162, found: 90 })));
+	}
+}
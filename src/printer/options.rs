@@ -0,0 +1,147 @@
+//! Settings that control how the printer handles a print job
+
+use super::geometry::Resolution;
+
+/// Flags sent to the printer along with each print job
+///
+/// The defaults match what Brother's own drivers send: every validation and quality flag enabled, the label cut
+/// after each page, and standard resolution.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PrintOptions {
+	/// Prioritize print quality over print speed
+	pub quality_priority: bool,
+	/// Have the printer recover automatically from errors such as the cover being opened while printing
+	pub printer_recovery: bool,
+	/// Have the printer reject the job if the loaded media type (continuous or die-cut) differs from the job's
+	pub validate_media_type: bool,
+	/// Have the printer reject the job if the loaded media width differs from the job's
+	pub validate_media_width: bool,
+	/// Have the printer reject the job if the loaded media length differs from the job's
+	pub validate_media_length: bool,
+	/// Cut the label after each page
+	pub auto_cut: bool,
+	/// Cut the label after the last page
+	pub cut_at_end: bool,
+	/// Print at 300 x 600 dpi. Raster lines are then twice as dense in the feed direction.
+	pub high_resolution: bool,
+	/// Mirror the printed image along the feed direction
+	pub mirror: bool,
+	/// Length of blank tape fed before and after the content, in dots. `None` uses the loaded label's default.
+	/// Ignored by the printer for die-cut labels.
+	pub margin: Option<u16>,
+	/// Reject raster lines that print outside of the loaded label's printable area. Disable this to print on the
+	/// second row of 12mm tape, e.g. with `TextRasterizer::set_second_row_image()`.
+	pub check_printable_area: bool,
+}
+
+impl Default for PrintOptions {
+	fn default() -> Self {
+		Self {
+			quality_priority: true,
+			printer_recovery: true,
+			validate_media_type: true,
+			validate_media_width: true,
+			validate_media_length: true,
+			auto_cut: true,
+			cut_at_end: true,
+			high_resolution: false,
+			mirror: false,
+			margin: None,
+			check_printable_area: true,
+		}
+	}
+}
+
+impl PrintOptions {
+	/// The resolution raster lines are printed at with these options
+	pub fn resolution(&self) -> Resolution {
+		if self.high_resolution {
+			Resolution::High
+		}
+		else {
+			Resolution::Standard
+		}
+	}
+
+	/// Valid flags byte of the print information command (`ESC i z`)
+	pub(crate) fn print_information_flags(&self) -> u8 {
+		let mut flags = 0;
+		if self.validate_media_type {
+			flags |= 0x02;
+		}
+		if self.validate_media_width {
+			flags |= 0x04;
+		}
+		if self.validate_media_length {
+			flags |= 0x08;
+		}
+		if self.quality_priority {
+			flags |= 0x40;
+		}
+		if self.printer_recovery {
+			flags |= 0x80;
+		}
+		flags
+	}
+
+	/// Argument of the various mode command (`ESC i M`)
+	pub(crate) fn various_mode(&self) -> u8 {
+		let mut mode = 0;
+		if self.auto_cut {
+			mode |= 1 << 6;
+		}
+		if self.mirror {
+			mode |= 1 << 7;
+		}
+		mode
+	}
+
+	/// Argument of the expanded mode command (`ESC i K`)
+	pub(crate) fn expanded_mode(&self) -> u8 {
+		let mut mode = 0;
+		if self.cut_at_end {
+			mode |= 1 << 3;
+		}
+		if self.high_resolution {
+			mode |= 1 << 6;
+		}
+		mode
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::PrintOptions;
+
+	#[test]
+	fn default_flags() {
+		// The bytes sent before print options were configurable
+		let options = PrintOptions::default();
+		assert_eq!(options.print_information_flags(), 0xCE);
+		assert_eq!(options.various_mode(), 0x40);
+		assert_eq!(options.expanded_mode(), 0x08);
+	}
+
+	#[test]
+	fn flag_bits() {
+		let options = PrintOptions { high_resolution: true, ..PrintOptions::default() };
+		assert_eq!(options.expanded_mode(), 0x48);
+		let options = PrintOptions { high_resolution: true, cut_at_end: false, ..PrintOptions::default() };
+		assert_eq!(options.expanded_mode(), 0x40);
+		let options = PrintOptions { auto_cut: false, cut_at_end: false, ..PrintOptions::default() };
+		assert_eq!(options.various_mode(), 0x00);
+		assert_eq!(options.expanded_mode(), 0x00);
+		let options = PrintOptions { mirror: true, ..PrintOptions::default() };
+		assert_eq!(options.various_mode(), 0xC0);
+		let options = PrintOptions {
+			quality_priority: false,
+			printer_recovery: false,
+			validate_media_type: false,
+			validate_media_width: false,
+			validate_media_length: true,
+			..PrintOptions::default()
+		};
+		assert_eq!(options.print_information_flags(), 0x08);
+	}
+}
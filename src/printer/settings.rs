@@ -0,0 +1,168 @@
+//! Persistent printer settings, such as auto power-off and auto power-on
+//!
+//! These use the printer settings commands (`ESC i U`) documented in the command references for the QL-800 and
+//! QL-1100 series. Other models don't implement them.
+
+use std::time::{ Duration, Instant };
+use super::{ status, Error, Result, ThermalPrinter };
+
+const WRITE_SETTING: u8 = 0x77;
+const READ_SETTING: u8 = 0x72;
+
+const AUTO_POWER_ON: u8 = 0x70;
+const AUTO_POWER_OFF: u8 = 0x71;
+
+/// Auto power-off delays supported by the printer, in minutes. `0` disables auto power-off.
+pub const AUTO_POWER_OFF_MINUTES: [u8; 7] = [0, 10, 20, 30, 40, 50, 60];
+
+impl<T: rusb::UsbContext> ThermalPrinter<T> {
+	/// Get the number of idle minutes after which the printer turns itself off. `0` means auto power-off is disabled.
+	pub fn auto_power_off(&self) -> Result<u8> {
+		power_off_minutes(self.read_setting(AUTO_POWER_OFF)?)
+	}
+
+	/// Set the number of idle minutes after which the printer turns itself off. Must be one of
+	/// `AUTO_POWER_OFF_MINUTES`; `0` disables auto power-off.
+	pub fn set_auto_power_off(&self, minutes: u8) -> Result<()> {
+		self.write_setting(AUTO_POWER_OFF, power_off_value(minutes)?)
+	}
+
+	/// Get whether the printer turns on automatically when it is connected to power
+	pub fn auto_power_on(&self) -> Result<bool> {
+		Ok(self.read_setting(AUTO_POWER_ON)? != 0)
+	}
+
+	/// Set whether the printer turns on automatically when it is connected to power
+	pub fn set_auto_power_on(&self, enabled: bool) -> Result<()> {
+		self.write_setting(AUTO_POWER_ON, enabled as u8)
+	}
+
+	fn check_settings_support(&self) -> Result<()> {
+		match self.printer_model() {
+			Some(model) if model.supports_settings() => Ok(()),
+			_ => Err(Error::Unsupported("This printer model does not support printer settings commands")),
+		}
+	}
+
+	fn write_setting(&self, setting: u8, value: u8) -> Result<()> {
+		self.check_settings_support()?;
+		let cancellation = self.cancellation(None);
+		let _sending = self.lock_sending();
+		// A `cancel()` while waiting for a job on another thread to finish interrupts this too
+		if cancellation.is_cancelled() {
+			return Err(Error::Cancelled);
+		}
+		self.write(&write_setting_command(setting, value))
+	}
+
+	fn read_setting(&self, setting: u8) -> Result<u8> {
+		// Waits for the reply, skipping status messages the printer pushes in the meantime
+		const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+		self.check_settings_support()?;
+		let cancellation = self.cancellation(None);
+		// Held until the reply arrives, so a job being waited on can't miss its status messages
+		let _sending = self.lock_sending();
+		if cancellation.is_cancelled() {
+			return Err(Error::Cancelled);
+		}
+		self.write(&read_setting_command(setting))?;
+
+		let deadline = Instant::now() + REPLY_TIMEOUT;
+		loop {
+			if cancellation.is_cancelled() {
+				return Err(Error::Cancelled);
+			}
+			let mut reply = [0; status::FRAME_SIZE];
+			let timeout = deadline.saturating_duration_since(Instant::now());
+			let bytes_read = match self.handle.read_bulk(self.in_endpoint, &mut reply, timeout) {
+				Err(rusb::Error::Timeout) => return Err(Error::Protocol("Printer did not reply to settings request")),
+				result => result?,
+			};
+			if let Some(value) = parse_setting_reply(&reply[..bytes_read])? {
+				return Ok(value);
+			}
+		}
+	}
+}
+
+/// The write command of the printer settings commands (`ESC i U w`)
+fn write_setting_command(setting: u8, value: u8) -> [u8; 7] {
+	[0x1B, 0x69, 0x55, WRITE_SETTING, 0x01, setting, value]
+}
+
+/// The read command of the printer settings commands (`ESC i U r`)
+fn read_setting_command(setting: u8) -> [u8; 6] {
+	[0x1B, 0x69, 0x55, READ_SETTING, 0x01, setting]
+}
+
+/// Extracts the setting's value from a reply to `read_setting_command()`, which is the last byte of a 32 byte frame
+/// like a status message. Returns `None` for status messages the printer sent on its own.
+fn parse_setting_reply(reply: &[u8]) -> Result<Option<u8>> {
+	// Byte 18 of a status message is its type, which is 0 for replies
+	const STATUS_TYPE: usize = 18;
+
+	if reply.len() != status::FRAME_SIZE || reply[0] != 0x80 {
+		return Err(Error::Protocol("Invalid reply to settings request"));
+	}
+	if reply[STATUS_TYPE] != 0x00 {
+		return Ok(None);
+	}
+	Ok(Some(reply[status::FRAME_SIZE - 1]))
+}
+
+/// The auto power-off setting's value for a delay in minutes
+fn power_off_value(minutes: u8) -> Result<u8> {
+	AUTO_POWER_OFF_MINUTES.iter()
+		.position(|&supported| supported == minutes)
+		.map(|value| value as u8)
+		.ok_or(Error::InvalidArgument("Auto power-off must be 0, 10, 20, 30, 40, 50, or 60 minutes"))
+}
+
+/// The auto power-off delay in minutes for the setting's value
+fn power_off_minutes(value: u8) -> Result<u8> {
+	AUTO_POWER_OFF_MINUTES.get(value as usize)
+		.copied()
+		.ok_or(Error::Protocol("Printer reported an invalid auto power-off setting"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn setting_commands() {
+		assert_eq!(write_setting_command(AUTO_POWER_OFF, 3), [0x1B, 0x69, 0x55, 0x77, 0x01, 0x71, 0x03]);
+		assert_eq!(write_setting_command(AUTO_POWER_ON, 1), [0x1B, 0x69, 0x55, 0x77, 0x01, 0x70, 0x01]);
+		assert_eq!(read_setting_command(AUTO_POWER_OFF), [0x1B, 0x69, 0x55, 0x72, 0x01, 0x71]);
+	}
+
+	#[test]
+	fn setting_replies() {
+		let mut reply = [0; 32];
+		reply[0] = 0x80;
+		reply[31] = 0x04;
+		assert_eq!(parse_setting_reply(&reply).unwrap(), Some(0x04));
+
+		// A phase change pushed by the printer isn't the reply
+		let mut phase_change = reply;
+		phase_change[18] = 0x06;
+		assert_eq!(parse_setting_reply(&phase_change).unwrap(), None);
+
+		assert!(matches!(parse_setting_reply(&reply[..8]), Err(Error::Protocol(_))));
+		let mut wrong_header = reply;
+		wrong_header[0] = 0x00;
+		assert!(matches!(parse_setting_reply(&wrong_header), Err(Error::Protocol(_))));
+	}
+
+	#[test]
+	fn auto_power_off_values() {
+		for (value, &minutes) in AUTO_POWER_OFF_MINUTES.iter().enumerate() {
+			assert_eq!(power_off_value(minutes).unwrap(), value as u8);
+			assert_eq!(power_off_minutes(value as u8).unwrap(), minutes);
+		}
+		assert_eq!(power_off_value(30).unwrap(), 3);
+		assert!(matches!(power_off_value(15), Err(Error::InvalidArgument(_))));
+		assert!(matches!(power_off_minutes(7), Err(Error::Protocol(_))));
+	}
+}
@@ -0,0 +1,176 @@
+//! P-touch Template mode, in which label layouts stored in the printer are filled in and printed
+//!
+//! Templates are created with P-touch Editor and transferred to the printer with the P-touch Transfer Manager.
+//! Based on the published P-touch Template command reference.
+
+use super::{ Error, Result, ThermalPrinter };
+
+/// The command set the printer interprets incoming data with
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommandMode {
+	EscP,
+	Raster,
+	PTouchTemplate,
+}
+impl CommandMode {
+	fn code(self) -> u8 {
+		match self {
+			CommandMode::EscP => 0x00,
+			CommandMode::Raster => 0x01,
+			CommandMode::PTouchTemplate => 0x03,
+		}
+	}
+}
+
+/// Identifies an object (e.g. a text field or barcode) within a template
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TemplateObject<'a> {
+	/// The object name assigned in P-touch Editor
+	Name(&'a str),
+	/// The object number, starting from 1
+	Index(u16),
+}
+
+/// `^II` (initialize) followed by `^TS` selecting template `number`
+fn select_template_command(number: u8) -> Result<Vec<u8>> {
+	if number == 0 {
+		return Err(Error::InvalidArgument("Template numbers start at 1"));
+	}
+	let mut command = b"^II".to_vec();
+	command.extend_from_slice(format!("^TS{:03}", number).as_bytes());
+	Ok(command)
+}
+
+/// `^ON` selecting an object by its NUL-terminated name, or `^OS` selecting it by number
+fn select_object_command(object: TemplateObject<'_>) -> Result<Vec<u8>> {
+	match object {
+		TemplateObject::Name(name) => {
+			if name.contains('\0') {
+				return Err(Error::InvalidArgument("Template object names can't contain NUL characters"));
+			}
+			let mut command = b"^ON".to_vec();
+			command.extend_from_slice(name.as_bytes());
+			command.push(0x00);
+			Ok(command)
+		},
+		TemplateObject::Index(index) => {
+			if index == 0 || index > 999 {
+				return Err(Error::InvalidArgument("Template object numbers must be between 1 and 999"));
+			}
+			Ok(format!("^OS{:03}", index).into_bytes())
+		},
+	}
+}
+
+/// `^DI` followed by the data's length as a little-endian `u16` and the data itself
+fn object_data_command(data: &str) -> Result<Vec<u8>> {
+	if data.len() > u16::MAX as usize {
+		return Err(Error::InvalidArgument("Template object data is limited to 65535 bytes"));
+	}
+	let mut command = b"^DI".to_vec();
+	command.extend_from_slice(&(data.len() as u16).to_le_bytes());
+	command.extend_from_slice(data.as_bytes());
+	Ok(command)
+}
+
+const PRINT_TEMPLATE_COMMAND: &[u8] = b"^FF";
+
+impl<T: rusb::UsbContext> ThermalPrinter<T> {
+	/// Switches the command set the printer interprets. `print()` switches back to `CommandMode::Raster` on its own.
+	pub fn set_command_mode(&self, mode: CommandMode) -> Result<()> {
+		if mode == CommandMode::PTouchTemplate {
+			self.check_template_support()?;
+		}
+		let _sending = self.lock_sending();
+		self.write(&[0x1B, 0x69, 0x61, mode.code()])
+	}
+
+	/// Selects a template stored in the printer by its key assignment (1 through 255) and resets any object data
+	/// set previously. Requires `CommandMode::PTouchTemplate`.
+	pub fn select_template(&self, number: u8) -> Result<()> {
+		let command = select_template_command(number)?;
+		let _sending = self.lock_sending();
+		self.write(&command)
+	}
+
+	/// Selects the object that the next `set_object_data()` call fills in
+	pub fn select_object(&self, object: TemplateObject<'_>) -> Result<()> {
+		let command = select_object_command(object)?;
+		let _sending = self.lock_sending();
+		self.write(&command)
+	}
+
+	/// Replaces the content of the selected object. The data is sent as-is, so it must be in an encoding the
+	/// printer's template expects.
+	pub fn set_object_data(&self, data: &str) -> Result<()> {
+		let command = object_data_command(data)?;
+		let _sending = self.lock_sending();
+		self.write(&command)
+	}
+
+	/// Prints the selected template with the object data set so far
+	pub fn print_template(&self) -> Result<()> {
+		let _sending = self.lock_sending();
+		self.write(PRINT_TEMPLATE_COMMAND)
+	}
+
+	/// Switches to P-touch Template mode, fills in the objects of template `number`, and prints it
+	///
+	/// The commands are sent without interruption so a print on another thread can't interleave with them.
+	pub fn fill_template(&self, number: u8, objects: &[(TemplateObject<'_>, &str)]) -> Result<()> {
+		self.check_template_support()?;
+		// Encode everything first so invalid arguments don't leave a template half filled in
+		let mut commands = vec![
+			vec![0x1B, 0x69, 0x61, CommandMode::PTouchTemplate.code()],
+			select_template_command(number)?,
+		];
+		for (object, data) in objects {
+			commands.push(select_object_command(*object)?);
+			commands.push(object_data_command(data)?);
+		}
+		commands.push(PRINT_TEMPLATE_COMMAND.to_vec());
+
+		let _sending = self.lock_sending();
+		for command in commands {
+			self.write(&command)?;
+		}
+		Ok(())
+	}
+
+	fn check_template_support(&self) -> Result<()> {
+		match self.printer_model() {
+			Some(model) if model.supports_template_mode() => Ok(()),
+			_ => Err(Error::Unsupported("This printer model does not support P-touch Template mode")),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn template_commands() {
+		assert_eq!(select_template_command(7).unwrap(), b"^II^TS007");
+		assert_eq!(select_template_command(255).unwrap(), b"^II^TS255");
+		assert!(matches!(select_template_command(0), Err(Error::InvalidArgument(_))));
+
+		assert_eq!(select_object_command(TemplateObject::Name("Title")).unwrap(), b"^ONTitle\0");
+		assert_eq!(select_object_command(TemplateObject::Index(12)).unwrap(), b"^OS012");
+		assert!(matches!(select_object_command(TemplateObject::Name("a\0b")), Err(Error::InvalidArgument(_))));
+		assert!(matches!(select_object_command(TemplateObject::Index(0)), Err(Error::InvalidArgument(_))));
+		assert!(matches!(select_object_command(TemplateObject::Index(1000)), Err(Error::InvalidArgument(_))));
+	}
+
+	#[test]
+	fn object_data() {
+		assert_eq!(object_data_command("Hi").unwrap(), b"^DI\x02\x00Hi");
+		// The length is a little-endian u16
+		let data = "x".repeat(300);
+		let command = object_data_command(&data).unwrap();
+		assert_eq!(&command[..5], &[b'^', b'D', b'I', 0x2C, 0x01]);
+		assert_eq!(command.len(), 305);
+		assert!(object_data_command(&"x".repeat(u16::MAX as usize)).is_ok());
+		assert!(matches!(object_data_command(&"x".repeat(u16::MAX as usize + 1)), Err(Error::InvalidArgument(_))));
+	}
+}
@@ -0,0 +1,272 @@
+//! Conversion of images into raster lines that can be printed by a `ThermalPrinter`
+
+use image::{ DynamicImage, GenericImageView, GrayImage, Luma };
+use crate::printer::constants::{ Label, LabelShape };
+use crate::printer::geometry::{ Axis, PrintHead, Resolution };
+use crate::printer::RasterLine;
+use crate::error::{ Error, Result };
+
+mod adjust;
+pub mod dither;
+pub mod options;
+
+use options::{ Orientation, RasterOptions };
+
+/// How `ImageRasterizer` sizes an image to the label's printable area
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scaling {
+    /// Scale the image up or down as far as it fits, keeping its aspect ratio. On continuous tape, the image fills
+    /// the width of the tape and the label is as long as needed.
+    Fit,
+    /// Scale down images that don't fit, keeping their aspect ratio, but print smaller images at their original size
+    ShrinkToFit,
+    /// Print one image pixel per dot. Parts of the image that don't fit on the label are cut off.
+    None,
+}
+
+/// Converts an image to raster lines for `head`. The image is placed across the tape with its first row on the pin at
+/// `offset`, and each column becomes one raster line.
+pub(crate) fn image_to_raster_lines(mut image: GrayImage, head: PrintHead, offset: u32, options: &RasterOptions) -> Result<Vec<RasterLine>> {
+    adjust::adjust(&mut image, options)?;
+    dither::dither(&mut image, options);
+    // We need to sidescan this generated image for the printer
+    let mut lines = Vec::with_capacity(image.width() as usize);
+    for c in 0..image.width() {
+        let mut line = vec![0; head.bytes_per_line()];
+        for r in 0..image.height() {
+            let pin = offset + r;
+            if pin >= head.pins() {
+                break;
+            }
+            let luma_pixel = image.get_pixel(c, r);
+            if luma_pixel[0] <= 0xFF / 2 {
+                // Pins are numbered starting with the most significant bit
+                line[pin as usize / 8] |= 0x80 >> (pin % 8);
+            }
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Mirrors and rotates content laid out upright onto the label as set by `options`
+pub(crate) fn orient(image: GrayImage, options: &RasterOptions) -> GrayImage {
+    let image = if options.mirror {
+        image::imageops::flip_horizontal(&image)
+    }
+    else {
+        image
+    };
+    match options.orientation {
+        Orientation::Rotate0 => image,
+        Orientation::Rotate90 => image::imageops::rotate90(&image),
+        Orientation::Rotate180 => image::imageops::rotate180(&image),
+        Orientation::Rotate270 => image::imageops::rotate270(&image),
+    }
+}
+
+/// Blanks every pixel outside of the ellipse inscribed in the image, for labels with a round printable area
+pub(crate) fn clip_to_ellipse(image: &mut GrayImage) {
+    let radius_x = image.width() as f32 / 2.0;
+    let radius_y = image.height() as f32 / 2.0;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = (x as f32 + 0.5 - radius_x) / radius_x;
+        let dy = (y as f32 + 0.5 - radius_y) / radius_y;
+        if dx * dx + dy * dy > 1.0 {
+            *pixel = Luma([255]);
+        }
+    }
+}
+
+/// Converts an image to grayscale, composited onto a white background where it is transparent
+fn flatten(image: &DynamicImage) -> GrayImage {
+    let rgba = image.to_rgba();
+    GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [red, green, blue, alpha] = rgba.get_pixel(x, y).0;
+        // Same luma weights as the image crate uses
+        let luma = (2126 * red as u32 + 7152 * green as u32 + 722 * blue as u32) / 10000;
+        let alpha = alpha as u32;
+        Luma([((luma * alpha + 0xFF * (0xFF - alpha)) / 0xFF) as u8])
+    })
+}
+
+/// Convert any image (e.g. a PNG logo or generated graphic) into raster lines that can be printed by a
+/// `ThermalPrinter`
+///
+/// The image is scaled to the label's printable area, centered on it, and converted to black and white as set by its
+/// `RasterOptions`. Transparent areas are left blank.
+pub struct ImageRasterizer {
+    label: Label,
+    print_head: PrintHead,
+    resolution: Resolution,
+    scaling: Scaling,
+    auto_rotate: bool,
+    raster_options: RasterOptions,
+}
+impl ImageRasterizer {
+    /// The image rasterizer needs to know the loaded label media in order to size and place images on it. Images
+    /// are fit to the printable area and rotated if that lets them be printed larger.
+    pub fn new(label: Label) -> Self {
+        Self {
+            label,
+            print_head: PrintHead::Standard,
+            resolution: Resolution::Standard,
+            scaling: Scaling::Fit,
+            auto_rotate: true,
+            raster_options: RasterOptions::default(),
+        }
+    }
+    /// Raster lines are rendered for printers with a standard print head by default. Set the print head of the
+    /// printer they will be sent to (from `ThermalPrinter::print_head()`) so that the content lands on the
+    /// label's printable area.
+    pub fn set_print_head(&mut self, print_head: PrintHead) {
+        self.print_head = print_head;
+    }
+    /// Raster lines are rendered for standard resolution by default. When printing with
+    /// `PrintOptions::high_resolution`, set `Resolution::High` so that images keep their aspect ratio.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+    }
+    /// Images are scaled with `Scaling::Fit` by default
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+    /// Images are drawn with their top edge along the tape. With auto-rotation enabled (the default), they are
+    /// turned by 90 degrees instead when that lets them be printed larger, e.g. portrait images on continuous tape.
+    /// Images are never auto-rotated when `RasterOptions::orientation` is set.
+    pub fn set_auto_rotate(&mut self, auto_rotate: bool) {
+        self.auto_rotate = auto_rotate;
+    }
+    /// By default, images are drawn upright and pixels darker than 50% gray are printed. Photos and gradients look
+    /// better with one of the dithering algorithms, and scanned images often need their levels or contrast adjusted.
+    pub fn set_raster_options(&mut self, raster_options: RasterOptions) {
+        self.raster_options = raster_options;
+    }
+
+    /// Transforms an image into raster lines ready to send to the `ThermalPrinter`. Continuous tape is cut to the
+    /// length of the scaled image, while die-cut labels always get their full printable length.
+    pub fn rasterize(&self, image: &DynamicImage) -> Result<Vec<RasterLine>> {
+        let canvas = self.render(image)?;
+        image_to_raster_lines(canvas, self.print_head, self.label.head_offset(self.print_head), &self.raster_options)
+    }
+
+    /// Draws the image onto a grayscale canvas covering the printable area, with x along the tape and y across it
+    fn render(&self, image: &DynamicImage) -> Result<GrayImage> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err(Error::InvalidArgument("Image has no pixels"));
+        }
+        let mut image = orient(flatten(image), &self.raster_options);
+        let upright = self.raster_options.orientation == Orientation::Rotate0;
+        if self.auto_rotate && upright && self.fit_scale(image.height(), image.width()) > self.fit_scale(image.width(), image.height()) {
+            image = image::imageops::rotate90(&image);
+        }
+
+        let scale = match self.scaling {
+            Scaling::Fit => self.fit_scale(image.width(), image.height()),
+            Scaling::ShrinkToFit => self.fit_scale(image.width(), image.height()).min(1.0),
+            Scaling::None => 1.0,
+        };
+        // Dots are closer together along the tape at high resolution
+        let length_scale = scale * self.resolution.dpi(Axis::Length) as f32 / self.resolution.dpi(Axis::Width) as f32;
+        let scaled_length = ((image.width() as f32 * length_scale).round() as u32).max(1);
+        let scaled_width = ((image.height() as f32 * scale).round() as u32).max(1);
+        if (scaled_length, scaled_width) != image.dimensions() {
+            image = image::imageops::resize(&image, scaled_length, scaled_width, image::FilterType::Triangle);
+        }
+
+        let printable = self.label.dots_printable_at(self.resolution);
+        let length = match printable.1 {
+            0 => scaled_length,
+            length => length,
+        };
+        let mut canvas = GrayImage::from_pixel(length, printable.0, Luma([255]));
+        // Center the image. Parts of it outside of the canvas are cut off.
+        let offset_x = (length as i64 - scaled_length as i64) / 2;
+        let offset_y = (printable.0 as i64 - scaled_width as i64) / 2;
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let canvas_x = x as i64 + offset_x;
+            let canvas_y = y as i64 + offset_y;
+            if canvas_x >= 0 && canvas_y >= 0 && canvas_x < length as i64 && canvas_y < printable.0 as i64 {
+                canvas.put_pixel(canvas_x as u32, canvas_y as u32, *pixel);
+            }
+        }
+
+        if self.label.shape == LabelShape::Round {
+            clip_to_ellipse(&mut canvas);
+        }
+        Ok(canvas)
+    }
+
+    /// The largest scale at which an image `length` pixels along the tape and `width` pixels across it fits the
+    /// printable area
+    fn fit_scale(&self, length: u32, width: u32) -> f32 {
+        let printable = self.label.dots_printable;
+        let scale = printable.0 as f32 / width as f32;
+        match printable.1 {
+            // Continuous tape is as long as it needs to be
+            0 => scale,
+            printable_length => scale.min(printable_length as f32 / length as f32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+
+    #[test]
+    fn rasterize_image() {
+        let continuous = constants::label_data(62, None).unwrap();
+        let mut rasterizer = ImageRasterizer::new(continuous);
+        // A black image fills pins 12 through 707, the printable width of the tape
+        let landscape = DynamicImage::ImageLuma8(GrayImage::new(200, 100));
+        let lines = rasterizer.rasterize(&landscape).unwrap();
+        assert_eq!(lines.len(), 1392);
+        assert!(lines.iter().all(|line| line.len() == 90 && line[0] == 0 && line[1] == 0x0F && line[88] == 0xF0 && line[89] == 0));
+
+        let portrait = DynamicImage::ImageLuma8(GrayImage::new(100, 200));
+        assert_eq!(rasterizer.rasterize(&portrait).unwrap().len(), 1392);
+        rasterizer.set_auto_rotate(false);
+        assert_eq!(rasterizer.rasterize(&portrait).unwrap().len(), 348);
+        rasterizer.set_resolution(Resolution::High);
+        assert_eq!(rasterizer.rasterize(&portrait).unwrap().len(), 696);
+
+        let transparent = DynamicImage::ImageRgba8(image::RgbaImage::new(10, 10));
+        assert!(rasterizer.rasterize(&transparent).unwrap().iter().flatten().all(|&byte| byte == 0));
+
+        let die_cut = constants::label_data(29, Some(90)).unwrap();
+        let mut rasterizer = ImageRasterizer::new(die_cut);
+        rasterizer.set_scaling(Scaling::ShrinkToFit);
+        let lines = rasterizer.rasterize(&DynamicImage::ImageLuma8(GrayImage::new(10, 10))).unwrap();
+        assert_eq!(lines.len(), die_cut.dots_printable.1 as usize);
+        assert_eq!(lines.iter().flatten().map(|byte| byte.count_ones()).sum::<u32>(), 100);
+    }
+
+    #[test]
+    fn raster_orientation() {
+        let label = constants::label_data(62, None).unwrap();
+        let mut rasterizer = ImageRasterizer::new(label);
+        let landscape = DynamicImage::ImageLuma8(GrayImage::new(200, 100));
+        rasterizer.set_raster_options(RasterOptions { orientation: Orientation::Rotate90, ..RasterOptions::default() });
+        assert_eq!(rasterizer.rasterize(&landscape).unwrap().len(), 348);
+        rasterizer.set_raster_options(RasterOptions { orientation: Orientation::Rotate180, ..RasterOptions::default() });
+        assert_eq!(rasterizer.rasterize(&landscape).unwrap().len(), 1392);
+
+        // Only the left half of the image is black
+        let half = DynamicImage::ImageLuma8(GrayImage::from_fn(20, 696, |x, _| Luma([if x < 10 { 0 } else { 0xFF }])));
+        let printed_lines = |rasterizer: &ImageRasterizer| -> Vec<bool> {
+            rasterizer.rasterize(&half).unwrap().iter().map(|line| line.iter().any(|&byte| byte != 0)).collect()
+        };
+        rasterizer.set_scaling(Scaling::None);
+        rasterizer.set_auto_rotate(false);
+        rasterizer.set_raster_options(RasterOptions::default());
+        let upright = printed_lines(&rasterizer);
+        assert!(upright[..10].iter().all(|&printed| printed) && !upright[10..].iter().any(|&printed| printed));
+        let reversed: Vec<bool> = upright.iter().rev().cloned().collect();
+        rasterizer.set_raster_options(RasterOptions { mirror: true, ..RasterOptions::default() });
+        assert_eq!(printed_lines(&rasterizer), reversed);
+        rasterizer.set_raster_options(RasterOptions { orientation: Orientation::Rotate180, ..RasterOptions::default() });
+        assert_eq!(printed_lines(&rasterizer), reversed);
+    }
+}
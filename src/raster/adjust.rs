@@ -0,0 +1,105 @@
+//! Tone and sharpness adjustments applied to grayscale images before they are converted to black and white
+
+use image::GrayImage;
+use crate::error::{ Error, Result };
+use super::options::RasterOptions;
+
+/// Fraction of pixels at either end of the histogram that auto-levels treats as outliers
+const AUTO_LEVELS_CLIP: f32 = 0.005;
+
+/// Applies the auto-levels, brightness, contrast, gamma and sharpening settings of `options` to `image`
+pub(crate) fn adjust(image: &mut GrayImage, options: &RasterOptions) -> Result<()> {
+    if options.gamma.is_nan() || options.gamma <= 0.0 {
+        return Err(Error::InvalidArgument("Gamma must be greater than 0"));
+    }
+    let (low, high) = if options.auto_levels {
+        levels(image)
+    }
+    else {
+        (0, 0xFF)
+    };
+    // Tone adjustments only depend on a pixel's own value, so they're computed once for every gray level
+    let tones: Vec<u8> = (0..=0xFF).map(|value| tone(value, low, high, options)).collect();
+    for pixel in image.pixels_mut() {
+        pixel[0] = tones[pixel[0] as usize];
+    }
+    if options.sharpen > 0.0 {
+        sharpen(image, options.sharpen);
+    }
+    Ok(())
+}
+
+/// The darkest and lightest gray levels of an image, ignoring a small fraction of outliers
+fn levels(image: &GrayImage) -> (u8, u8) {
+    let mut histogram = [0u32; 256];
+    for pixel in image.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let clip = (image.width() * image.height()) as f32 * AUTO_LEVELS_CLIP;
+    let mut count = 0;
+    let low = histogram.iter().position(|&pixels| {
+        count += pixels;
+        count as f32 > clip
+    }).unwrap_or(0);
+    count = 0;
+    let high = histogram.iter().rposition(|&pixels| {
+        count += pixels;
+        count as f32 > clip
+    }).unwrap_or(0xFF);
+    (low as u8, high as u8)
+}
+
+fn tone(value: u8, low: u8, high: u8, options: &RasterOptions) -> u8 {
+    let mut value = if high > low {
+        (value as f32 - low as f32) / (high - low) as f32
+    }
+    else {
+        value as f32 / 255.0
+    };
+    value = value.clamp(0.0, 1.0);
+    value = (value - 0.5) * options.contrast + 0.5 + options.brightness;
+    value = value.clamp(0.0, 1.0).powf(1.0 / options.gamma);
+    (value * 255.0).round() as u8
+}
+
+/// Unsharp masking: differences between each pixel and its blurred surroundings are amplified by `amount`
+fn sharpen(image: &mut GrayImage, amount: f32) {
+    let blurred = image::imageops::blur(image, 1.0);
+    for (pixel, blurred) in image.pixels_mut().zip(blurred.pixels()) {
+        let value = pixel[0] as f32 + amount * (pixel[0] as f32 - blurred[0] as f32);
+        pixel[0] = value.round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+    use crate::raster::{ ImageRasterizer, Scaling };
+
+    #[test]
+    fn raster_adjustments() {
+        let label = constants::label_data(62, None).unwrap();
+        let mut rasterizer = ImageRasterizer::new(label);
+        rasterizer.set_scaling(Scaling::None);
+        let gray = image::DynamicImage::ImageLuma8(GrayImage::from_pixel(10, 696, image::Luma([100])));
+        let printed_dots = |rasterizer: &ImageRasterizer| {
+            rasterizer.rasterize(&gray).unwrap().iter().flatten().map(|byte| byte.count_ones()).sum::<u32>()
+        };
+        assert_eq!(printed_dots(&rasterizer), 6960);
+
+        let lighter = [
+            RasterOptions { threshold: 100, ..RasterOptions::default() },
+            RasterOptions { brightness: 0.2, ..RasterOptions::default() },
+            RasterOptions { gamma: 2.0, ..RasterOptions::default() },
+            RasterOptions { contrast: 0.0, ..RasterOptions::default() },
+        ];
+        for options in &lighter {
+            rasterizer.set_raster_options(*options);
+            assert_eq!(printed_dots(&rasterizer), 0, "{:?}", options);
+        }
+
+        rasterizer.set_raster_options(RasterOptions { gamma: 0.0, ..RasterOptions::default() });
+        assert!(matches!(rasterizer.rasterize(&gray), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,138 @@
+//! Halftoning of grayscale images down to the black and white dots a thermal printer can print
+
+use image::{ GrayImage, Luma };
+use super::options::RasterOptions;
+
+/// How shades of gray are converted to black and white dots
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Dithering {
+    /// Pixels darker than `RasterOptions::threshold` are printed, all others are left blank. Best for text and line art.
+    Threshold,
+    /// Floyd–Steinberg error diffusion. A good default for photos.
+    FloydSteinberg,
+    /// Atkinson error diffusion, which only passes on part of the error. Gives more contrast than Floyd–Steinberg at
+    /// the cost of detail in very light and dark areas.
+    Atkinson,
+    /// Stucki error diffusion, which spreads the error further than Floyd–Steinberg for smoother gradients
+    Stucki,
+    /// Ordered dithering with a 4 x 4 Bayer matrix. Gives a regular crosshatch pattern that holds up well when the
+    /// printer bleeds neighbouring dots together.
+    Bayer4x4,
+    /// Ordered dithering with an 8 x 8 Bayer matrix, for more levels of gray than `Bayer4x4`
+    Bayer8x8,
+}
+
+/// Neighbours that receive part of a pixel's quantization error, as (x, y, weight) relative to the pixel
+type DiffusionKernel = &'static [(i32, u32, f32)];
+
+const FLOYD_STEINBERG: DiffusionKernel = &[
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0),
+];
+const ATKINSON: DiffusionKernel = &[
+    (1, 0, 1.0 / 8.0), (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0), (0, 1, 1.0 / 8.0), (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+const STUCKI: DiffusionKernel = &[
+    (1, 0, 8.0 / 42.0), (2, 0, 4.0 / 42.0),
+    (-2, 1, 2.0 / 42.0), (-1, 1, 4.0 / 42.0), (0, 1, 8.0 / 42.0), (1, 1, 4.0 / 42.0), (2, 1, 2.0 / 42.0),
+    (-2, 2, 1.0 / 42.0), (-1, 2, 2.0 / 42.0), (0, 2, 4.0 / 42.0), (1, 2, 2.0 / 42.0), (2, 2, 1.0 / 42.0),
+];
+
+/// Converts every pixel of `image` to either black or white. With serpentine scanning, error diffusion runs in
+/// alternating directions on every other row, which avoids the diagonal artifacts of always scanning left to right.
+pub(crate) fn dither(image: &mut GrayImage, options: &RasterOptions) {
+    let level = options.threshold as f32;
+    match options.dithering {
+        Dithering::Threshold => threshold(image, level),
+        Dithering::FloydSteinberg => diffuse_error(image, FLOYD_STEINBERG, options.serpentine, level),
+        Dithering::Atkinson => diffuse_error(image, ATKINSON, options.serpentine, level),
+        Dithering::Stucki => diffuse_error(image, STUCKI, options.serpentine, level),
+        Dithering::Bayer4x4 => ordered(image, 2),
+        Dithering::Bayer8x8 => ordered(image, 3),
+    }
+}
+
+fn quantize(value: f32, level: f32) -> u8 {
+    if value < level { 0 } else { 0xFF }
+}
+
+fn threshold(image: &mut GrayImage, level: f32) {
+    for pixel in image.pixels_mut() {
+        *pixel = Luma([quantize(pixel[0] as f32, level)]);
+    }
+}
+
+fn diffuse_error(image: &mut GrayImage, kernel: DiffusionKernel, serpentine: bool, level: f32) {
+    let (width, height) = image.dimensions();
+    let mut values: Vec<f32> = image.pixels().map(|pixel| pixel[0] as f32).collect();
+    for y in 0..height {
+        let reverse = serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let index = (y * width + x) as usize;
+            let value = values[index];
+            let quantized = quantize(value, level);
+            image.put_pixel(x, y, Luma([quantized]));
+
+            let error = value - quantized as f32;
+            for &(dx, dy, weight) in kernel {
+                // The kernel is mirrored when scanning right to left
+                let target_x = x as i32 + if reverse { -dx } else { dx };
+                let target_y = y + dy;
+                if target_x >= 0 && (target_x as u32) < width && target_y < height {
+                    values[(target_y * width + target_x as u32) as usize] += error * weight;
+                }
+            }
+        }
+    }
+}
+
+/// Index of a position in the Bayer matrix with 2^`order` rows and columns
+fn bayer_index(x: u32, y: u32, order: u32) -> u32 {
+    let mut index = 0;
+    // Each bit of the coordinates selects one of the quadrants of the 2 x 2 matrix [[0, 2], [3, 1]], with the lowest
+    // bits being the most significant
+    for bit in 0..order {
+        let bit_x = (x >> bit) & 1;
+        let bit_y = (y >> bit) & 1;
+        index = index * 4 + 2 * (bit_x ^ bit_y) + bit_y;
+    }
+    index
+}
+
+fn ordered(image: &mut GrayImage, order: u32) {
+    let levels = (1 << (2 * order)) as f32;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let threshold = (bayer_index(x, y, order) as f32 + 0.5) * 256.0 / levels;
+        *pixel = Luma([if (pixel[0] as f32) < threshold { 0 } else { 0xFF }]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dithering() {
+        let gray = GrayImage::from_pixel(64, 64, Luma([128]));
+        let black_fraction = |dithering| {
+            let mut image = gray.clone();
+            dither(&mut image, &RasterOptions { dithering, ..RasterOptions::default() });
+            assert!(image.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 0xFF));
+            image.pixels().filter(|pixel| pixel[0] == 0).count() as f32 / (64.0 * 64.0)
+        };
+        assert_eq!(black_fraction(Dithering::Threshold), 0.0);
+        assert_eq!(black_fraction(Dithering::Bayer4x4), 0.5);
+        assert_eq!(black_fraction(Dithering::Bayer8x8), 0.5);
+        for dithering in [Dithering::FloydSteinberg, Dithering::Atkinson, Dithering::Stucki] {
+            assert!((black_fraction(dithering) - 0.5).abs() < 0.05, "{:?}", dithering);
+        }
+
+        let mut black = GrayImage::new(16, 16);
+        dither(&mut black, &RasterOptions { dithering: Dithering::FloydSteinberg, serpentine: false, ..RasterOptions::default() });
+        assert!(black.pixels().all(|pixel| pixel[0] == 0));
+    }
+}
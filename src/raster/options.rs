@@ -0,0 +1,74 @@
+//! Settings that control how images are converted to raster lines
+
+use super::dither::Dithering;
+
+/// Rotation of the rendered content on the label, clockwise from the default orientation in which it reads along
+/// the tape
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Orientation {
+    Rotate0,
+    /// Reads across the tape, e.g. portrait content on a die-cut label
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+impl Orientation {
+    /// Whether content in this orientation is turned sideways, so that its width runs across the tape instead of
+    /// along it
+    pub fn swaps_axes(self) -> bool {
+        match self {
+            Orientation::Rotate0 | Orientation::Rotate180 => false,
+            Orientation::Rotate90 | Orientation::Rotate270 => true,
+        }
+    }
+}
+
+/// How a rendered image is turned into the black and white dots of raster lines
+///
+/// The content is first mirrored and rotated onto the label. The image is then adjusted in this order: auto-levels,
+/// brightness and contrast, gamma, sharpening. Finally, it is converted to black and white. The defaults leave the
+/// image unchanged and print every pixel darker than 50% gray.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct RasterOptions {
+    /// Rotation of the content on the label. Rasterizers lay content out to fit the label in this orientation.
+    pub orientation: Orientation,
+    /// Mirror the content left to right before it is rotated. Unlike `PrintOptions::mirror`, this is done while
+    /// rendering, so the raster lines themselves are mirrored.
+    pub mirror: bool,
+    /// How shades of gray are converted to black and white
+    pub dithering: Dithering,
+    /// Scan every other row in reverse when diffusing errors. Only used by the error diffusion algorithms.
+    pub serpentine: bool,
+    /// Pixels darker than this gray level (0 to 255) are printed. Used by `Dithering::Threshold` and the error
+    /// diffusion algorithms. Lower it to thin out antialiased edges, raise it to make them bolder.
+    pub threshold: u8,
+    /// Stretch the image's gray levels so that its darkest pixels become black and its lightest white
+    pub auto_levels: bool,
+    /// Lightens (up to 1.0) or darkens (down to -1.0) every pixel
+    pub brightness: f32,
+    /// Multiplies differences from 50% gray. 1.0 leaves the contrast unchanged.
+    pub contrast: f32,
+    /// Gamma correction. Values above 1.0 lighten midtones and values below 1.0 darken them.
+    pub gamma: f32,
+    /// Strength of the unsharp mask applied to the image. 0.0 disables sharpening and 1.0 is a moderate amount.
+    pub sharpen: f32,
+}
+
+impl Default for RasterOptions {
+    fn default() -> Self {
+        Self {
+            orientation: Orientation::Rotate0,
+            mirror: false,
+            dithering: Dithering::Threshold,
+            serpentine: true,
+            threshold: 128,
+            auto_levels: false,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            sharpen: 0.0,
+        }
+    }
+}
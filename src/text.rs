@@ -1,216 +1,444 @@
 //! Easy-to-use text and image compositing and rasterization for use with Brother QL printers
 
 use std::path::PathBuf;
-use std::fs;
-use rusttype::{ Scale, Point, Font };
-use image::{ DynamicImage, Luma };
-use crate::printer::constants::Label;
+use std::sync::Arc;
+use rusttype::{ Scale, Point, Font, Rect, SharedBytes };
+use image::{ GrayImage, Luma };
+use crate::printer::constants::{ Label, LabelShape };
+use crate::printer::geometry::PrintHead;
+use crate::printer::RasterLine;
+use crate::raster::{ clip_to_ellipse, image_to_raster_lines, orient };
+use crate::raster::options::RasterOptions;
+use crate::error::{ Error, Result };
+
+pub(crate) mod font;
+pub(crate) mod layout;
+#[cfg(feature = "shaping")]
+pub(crate) mod shaping;
+pub mod style;
+
+use font::{ CachedFont, ChainGlyph, FontChain };
+use layout::Paragraph;
+use style::{ HorizontalAlignment, Padding, TextStyle, VerticalAlignment };
 
 type XY<T> = Point<T>;
 
-fn calc_text_width(glyphs: &[rusttype::PositionedGlyph]) -> u32 {
-    let min_x = glyphs
-        .first()
-        .map(|g| g.pixel_bounding_box().unwrap().min.x)
-        .unwrap();
-    let max_x = glyphs
-        .last()
-        .map(|g| g.pixel_bounding_box().unwrap().max.x)
-        .unwrap();
-    (max_x - min_x) as u32
+/// Environment variable with a path that each rendered label is saved to as an image, for debugging
+const RENDER_PATH_VARIABLE: &str = "BROTHER_QL_RENDER_PATH";
+
+/// Horizontal position of the leftmost inked pixel of the glyphs, and the width of all of their ink
+fn calc_text_bounds(glyphs: &[ChainGlyph]) -> (i32, u32) {
+    // Glyphs without any pixels (e.g. spaces) don't have a bounding box
+    let bounding_boxes: Vec<_> = glyphs.iter().filter_map(|(_, g)| g.pixel_bounding_box()).collect();
+    let min_x = bounding_boxes.iter().map(|bounding_box| bounding_box.min.x).min();
+    let max_x = bounding_boxes.iter().map(|bounding_box| bounding_box.max.x).max();
+    match (min_x, max_x) {
+        (Some(min_x), Some(max_x)) => (min_x, (max_x - min_x) as u32),
+        _ => (0, 0),
+    }
 }
 
-struct ResizedText<'a> {
+struct ResizedText {
     rendered_size: XY<u32>,
-    glyphs: Vec<rusttype::PositionedGlyph<'a>>,
+    /// Distance from where the text is laid out to its first inked pixel
+    left: i32,
+    glyphs: Vec<ChainGlyph>,
 }
-impl<'a> ResizedText<'a> {
-    pub fn create<'b>(font: &'a Font, text: &'b str, max_width: u32, max_font_size: f32) -> Self {
-        let mut font_size = max_font_size.ceil(); // Max possible font size
+impl ResizedText {
+    /// Lays out a line of text with the characters each font lacks taken from the next font in the chain, and
+    /// measures the result. The font size starts at `max_font_size` or `size_limit`, whichever is smaller.
+    pub fn create(fonts: &FontChain, text: &str, max_width: u32, max_font_size: f32, size_limit: u32) -> Result<Self> {
+        let mut font_size = max_font_size.min(size_limit as f32).ceil(); // Max possible font size
         let rendered_size;
+        let left;
         // Scale the font size down until it all fits length-wise
         let glyphs = loop {
+            if font_size < 1.0 {
+                return Err(Error::Rasterization(format!("\"{}\" does not fit in {} dots at any font size", text, max_width)));
+            }
             let scale = Scale::uniform(font_size);
-            let v_metrics = font.v_metrics(scale);
-            let glyphs: Vec<_> = font.layout(text, scale, Point { x: 0.0, y: v_metrics.ascent }).collect();
+            let v_metrics = fonts.v_metrics(text, scale);
+            let glyphs = fonts.layout(text, scale, Point { x: 0.0, y: v_metrics.ascent });
 
-            let width = calc_text_width(&glyphs);
+            let (ink_left, width) = calc_text_bounds(&glyphs);
             if width < max_width {
                 let height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
                 rendered_size = XY { x: width, y: height };
+                left = ink_left;
                 break glyphs;
             }
             font_size -= 1.0;
         };
 
-        Self {
+        Ok(Self {
             rendered_size,
+            left,
             glyphs,
-        }
+        })
     }
 }
 
-fn draw_glyphs(image: &mut image::GrayImage, glyphs: &[rusttype::PositionedGlyph], offset: XY<i32>, invert: bool) {
-    for glyph in glyphs {
-        if let Some(bounding_box) = glyph.pixel_bounding_box() {
-            // Draw the glyph into the image per-pixel by using the draw closure
-            glyph.draw(|x, y, v| {
-                let color = if invert {
-                    (255.0 * v) as u8
+fn draw_glyphs(image: &mut GrayImage, fonts: &FontChain, glyphs: &[ChainGlyph], offset: XY<i32>, invert: bool) {
+    for (font, glyph) in glyphs {
+        let (position, rendered) = fonts.render(*font, glyph);
+        for y in 0..rendered.height {
+            for x in 0..rendered.width {
+                let coverage = rendered.coverage[(y * rendered.width + x) as usize];
+                // Offset the position by the glyph bounding box
+                let pixel_x = x as i32 + position.x + rendered.min.x + offset.x;
+                let pixel_y = y as i32 + position.y + rendered.min.y + offset.y;
+                // Parts of glyphs outside of the image are cut off
+                if pixel_x >= 0 && pixel_y >= 0 && (pixel_x as u32) < image.width() && (pixel_y as u32) < image.height() {
+                    let pixel = image.get_pixel_mut(pixel_x as u32, pixel_y as u32);
+                    // Overlapping glyphs keep each other's ink
+                    *pixel = if invert {
+                        Luma([pixel[0].max(coverage)])
+                    }
+                    else {
+                        Luma([pixel[0].min(255 - coverage)])
+                    };
                 }
-                else {
-                    255 - (255.0 * v) as u8
-                };
-
-                image.put_pixel(
-                    // Offset the position by the glyph bounding box
-                    (x as i32 + bounding_box.min.x + offset.x) as u32,
-                    (y as i32 + bounding_box.min.y + offset.y) as u32,
-                    // Turn the coverage into an alpha value
-                    Luma([color])
-                )
-            });
+            }
         }
     }
 }
 
-fn image_to_raster_lines(image: &image::GrayImage, width: u32) -> Vec<[u8; 90]> {
-    let width = width as usize;
-    let line_count = image.len() / width;
-
-    // We need to sidescan this generated image for the printer
-    let mut lines = Vec::with_capacity(width);
-    for c in 0..width {
-        let mut line = [0; 90]; // Always 90 for regular sized printers like the QL-700 (with a 0x00 byte to start)
-        let mut line_byte = 1;
-        // Bit index counts backwards
-        // First nibble (bits 7 through 4) in the second byte is blank
-        let mut line_bit_index: i8 = 3;
-        for r in 0..line_count {
-            line_bit_index -= 1;
-            if line_bit_index < 0 {
-                line_byte += 1;
-                line_bit_index += 8;
-            }
-            image.get_pixel(0, 0);
-            let luma_pixel = image.get_pixel(c as u32, r as u32); // + 3 was here in TS code -- not sure if needed
-            let value: u8 = if luma_pixel[0] > 0xFF / 2 {
-                0
+fn draw_text(image: &mut GrayImage, fonts: &FontChain, scale: Scale, text: &str, origin: XY<f32>, invert: bool) {
+    let glyphs = fonts.layout(text, scale, origin);
+    draw_glyphs(image, fonts, &glyphs, XY { x: 0, y: 0 }, invert);
+}
+
+/// Draws the lines of a paragraph into `area` as aligned by `style`
+fn draw_paragraph(image: &mut GrayImage, fonts: &FontChain, paragraph: &Paragraph, area: Rect<i32>, style: &TextStyle) {
+    let scale = paragraph.scale;
+    let free_height = area.height() as f32 - paragraph.height();
+    let top = area.min.y as f32 + match style.vertical_alignment {
+        VerticalAlignment::Top => 0.0,
+        VerticalAlignment::Middle => free_height / 2.0,
+        VerticalAlignment::Bottom => free_height,
+    };
+    for (index, line) in paragraph.lines.iter().enumerate() {
+        let baseline = top + paragraph.ascent + index as f32 * paragraph.line_height;
+        let free_width = area.width() as f32 - layout::measure(fonts, scale, &line.text);
+        let words = visual_words(&line.text);
+        if style.horizontal_alignment == HorizontalAlignment::Justify && !line.ends_paragraph && words.len() > 1 {
+            // Spread the free space evenly between the words
+            let gap = layout::measure(fonts, scale, " ") + free_width / (words.len() - 1) as f32;
+            let mut left = area.min.x as f32;
+            for word in words {
+                draw_text(image, fonts, scale, word, XY { x: left, y: baseline }, style.invert);
+                left += layout::measure(fonts, scale, word) + gap;
             }
-            else {
-                1
-            };
-            line[line_byte] |= value << line_bit_index;
+            continue;
+        }
+        let left = area.min.x as f32 + match style.horizontal_alignment {
+            HorizontalAlignment::Left | HorizontalAlignment::Justify => 0.0,
+            HorizontalAlignment::Center => free_width / 2.0,
+            HorizontalAlignment::Right => free_width,
+        };
+        draw_text(image, fonts, scale, &line.text, XY { x: left, y: baseline }, style.invert);
+    }
+}
+
+/// The words of a line, split at spaces, in the order they're displayed from left to right. Words of right-to-left
+/// runs are displayed last to first.
+#[cfg(feature = "shaping")]
+fn visual_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for word in text.split(' ') {
+        words.push((start, word));
+        start += word.len() + 1;
+    }
+    let mut ordered = Vec::with_capacity(words.len());
+    for (run, right_to_left) in shaping::visual_runs(text) {
+        let mut run_words: Vec<&str> = words.iter().filter(|(start, _)| run.contains(start)).map(|(_, word)| *word).collect();
+        if right_to_left {
+            run_words.reverse();
         }
-        lines.push(line);
+        ordered.extend(run_words);
     }
-    lines
+    // A word after a trailing space starts past the end of every run
+    ordered.extend(words.iter().filter(|(start, _)| *start >= text.len()).map(|(_, word)| *word));
+    ordered
+}
+
+/// The words of a line, split at spaces. Without shaping, text is always displayed from left to right.
+#[cfg(not(feature = "shaping"))]
+fn visual_words(text: &str) -> Vec<&str> {
+    text.split(' ').collect()
+}
+
+/// Shrinks `area` by `padding`
+fn pad(area: Rect<i32>, padding: &Padding) -> Result<Rect<i32>> {
+    let padded = Rect {
+        min: XY { x: area.min.x + padding.left.dots() as i32, y: area.min.y + padding.top.dots() as i32 },
+        max: XY { x: area.max.x - padding.right.dots() as i32, y: area.max.y - padding.bottom.dots() as i32 },
+    };
+    if padded.width() <= 0 || padded.height() <= 0 {
+        return Err(Error::InvalidArgument("The padding leaves no room for text"));
+    }
+    Ok(padded)
+}
+
+/// Forgets the fonts that `TextRasterizer`s loaded from files, so that the files are read and parsed again the
+/// next time text is rasterized
+///
+/// Fonts loaded from files are otherwise cached for the life of the process, and only read again once the file
+/// is modified. Call this to free their memory once they're no longer used.
+pub fn clear_font_cache() {
+    font::clear_cache();
+}
+
+/// Size of the image a label is rendered to, in dots
+struct Frame {
+    /// Along the tape
+    length: u32,
+    /// Across the tape, of the label's printable area
+    width: u32,
+    /// Where the second row of 12mm tape starts across the tape
+    secondary_top: u32,
+    /// Width of the second row of 12mm tape, or 0 if it isn't used
+    secondary_width: u32,
+}
+
+/// Where a `TextRasterizer` gets its font from
+enum FontSource {
+    /// Loaded when text is first rasterized, so that problems with the file are reported by `rasterize()`
+    Path(PathBuf),
+    Loaded(Arc<CachedFont>),
 }
 
 /// Easily convert text into a raster image that can be printed by a `ThermalPrinter`
+///
+/// Parsed fonts and rendered glyphs are cached, so the same rasterizer (or font file) can be reused for large
+/// batches of labels without parsing the font each time.
+///
+/// Set the `BROTHER_QL_RENDER_PATH` environment variable to a file path (e.g. `render.png`) to save each label
+/// as an image before it's converted to raster lines.
 pub struct TextRasterizer {
     label: Label,
-    font_path: PathBuf,
+    print_head: PrintHead,
+    font: FontSource,
+    fallback_fonts: Vec<FontSource>,
     second_row_image: Option<PathBuf>,
+    raster_options: RasterOptions,
 }
 impl TextRasterizer {
     /// The text rasterizer needs to know the loaded label media currently in the printer in order to resize and
-    /// shift the text content accordingly so that it will fit.
+    /// shift the text content accordingly so that it will fit. Each font file is only read and parsed once, even
+    /// when it is used by several rasterizers, until the file is modified or `clear_font_cache()` is called.
     pub fn new(label: Label, font_path: PathBuf) -> Self {
+        Self::with_font_source(label, FontSource::Path(font_path))
+    }
+    /// Creates a rasterizer that uses an already parsed font, which can be shared between rasterizers along with
+    /// the glyphs rendered from it.
+    ///
+    /// With the `shaping` feature, text in this font isn't shaped, since shaping needs the contents of the font
+    /// file: right-to-left text is still drawn in display order, but without ligatures, contextual forms or the
+    /// font's kerning tables. Use `new()` or `from_font_data()` for complex scripts.
+    pub fn from_font(label: Label, font: Arc<Font<'static>>) -> Self {
+        Self::with_font_source(label, FontSource::Loaded(CachedFont::shared(font)))
+    }
+    /// Creates a rasterizer from the contents of a TrueType or OpenType font file, e.g. a font embedded with
+    /// `include_bytes!()`
+    pub fn from_font_data<B: Into<SharedBytes<'static>>>(label: Label, font_data: B) -> Result<Self> {
+        let font = CachedFont::from_data(font_data.into())
+            .map_err(|err| Error::Rasterization(format!("Invalid font: {}", err)))?;
+        Ok(Self::with_font_source(label, FontSource::Loaded(Arc::new(font))))
+    }
+    fn with_font_source(label: Label, font: FontSource) -> Self {
         Self {
             label,
-            font_path,
-            second_row_image: None
+            print_head: PrintHead::Standard,
+            font,
+            fallback_fonts: Vec::new(),
+            second_row_image: None,
+            raster_options: RasterOptions::default(),
         }
     }
+    /// Adds a font file that characters missing from the rasterizer's font are drawn with, e.g. a font with CJK
+    /// characters or emoji. Fallback fonts are tried in the order they were added.
+    pub fn add_fallback_font_file(&mut self, font_path: PathBuf) {
+        self.fallback_fonts.push(FontSource::Path(font_path));
+    }
+    /// Adds an already parsed font that characters missing from the rasterizer's font are drawn with. Like fonts
+    /// passed to `from_font()`, it isn't shaped; use `add_fallback_font_file()` or `add_fallback_font_data()` for
+    /// complex scripts.
+    pub fn add_fallback_font(&mut self, font: Arc<Font<'static>>) {
+        self.fallback_fonts.push(FontSource::Loaded(CachedFont::shared(font)));
+    }
+    /// Adds the contents of a TrueType or OpenType font file that characters missing from the rasterizer's font are
+    /// drawn with
+    pub fn add_fallback_font_data<B: Into<SharedBytes<'static>>>(&mut self, font_data: B) -> Result<()> {
+        let font = CachedFont::from_data(font_data.into())
+            .map_err(|err| Error::Rasterization(format!("Invalid font: {}", err)))?;
+        self.fallback_fonts.push(FontSource::Loaded(Arc::new(font)));
+        Ok(())
+    }
+    /// Raster lines are rendered for printers with a standard print head by default. Set the print head of the
+    /// printer they will be sent to (from `ThermalPrinter::print_head()`) so that the content lands on the
+    /// label's printable area.
+    pub fn set_print_head(&mut self, print_head: PrintHead) {
+        self.print_head = print_head;
+    }
     /// Some types of label media (e.g. 12mm continuous tape) are wider than specified. Use this method to draw
     /// an image onto this second, normally out-of-bounds part of the tape. The bottom portion of the tape
     /// is usually pre-scored from the top part so consider this a way to make "bonus" labels with the same
-    /// amount of physical tape.
+    /// amount of physical tape. Since the image lies outside of the label's printable area, print the result with
+    /// `PrintOptions::check_printable_area` disabled.
     pub fn set_second_row_image(&mut self, path: PathBuf) {
         self.second_row_image = Some(path);
     }
+    /// By default, text reads along the tape and pixels darker than 50% gray are printed. Set the orientation to
+    /// print text across the tape, adjust the threshold to make antialiased text thinner or bolder, or use
+    /// dithering to keep the shading of a second row image.
+    pub fn set_raster_options(&mut self, raster_options: RasterOptions) {
+        self.raster_options = raster_options;
+    }
     /// Transforms text into a raster image ready to send to the `ThermalPrinter`. Typically, the text will appear
     /// as black on a white background. Enable the `invert` flag to print white text on a black background. Note that
     /// since the label is white, a faint border of white will still surround the label in areas that the printer
-    /// cannot print the black background.
-    pub fn rasterize(&self, text: &str, secondary_text: Option<&str>, font_scale: f32, invert: bool) -> Vec<[u8; 90]> {
-        let font_data = fs::read(&self.font_path).expect("Invalid font path");
-        let font: Font<'static> = Font::from_bytes(font_data).unwrap();
-
-        let mut length = 750;
-        let mut width;
-        let mut secondary_width = 0;
-
-        if self.label.tape_size.1 == 0 {
-            // Continuous tape
-            width = self.label.dots_printable.0 + self.label.right_margin as u32;
-
-            if self.label.tape_size.0 == 12 {
-                // 12mm label seems to need this for some reason
-                width += 25;
-                // 12mm labels have a second label below the primary that can actually be used
-				if self.second_row_image.is_some() {
-					secondary_width = 170;
-				}
-            }
-        }
-        else {
-            // Die cut labels
-            width = self.label.dots_printable.0 + self.label.right_margin as u32;
-            length = self.label.dots_printable.1;
-        }
-
-        let mut image = DynamicImage::new_luma8(length, width + secondary_width).to_luma();
-        // Set image background
-        for (_x, y, pixel) in image.enumerate_pixels_mut() {
-            if invert {
-                let top_label_size =
-                    self.label.dots_printable.0
-                    + self.label.right_margin as u32
-                    + 15;
-                if y > top_label_size {
-                    *pixel = Luma([255]); // Set to white
-                }
-                else {
-                    *pixel = Luma([0]); // Set to black
-                }
-            }
-            else {
-                *pixel = Luma([255]); // Set to white
-            }
+    /// cannot print the black background. `font_scale` multiplies the default font sizes, which are reduced until
+    /// each line fits across the label.
+    pub fn rasterize(&self, text: &str, secondary_text: Option<&str>, font_scale: f32, invert: bool) -> Result<Vec<RasterLine>> {
+        if !font_scale.is_finite() || font_scale <= 0.0 {
+            return Err(Error::InvalidArgument("The font scale must be a positive number"));
         }
+        let fonts = self.load_fonts()?;
+        let frame = self.frame();
+        let mut text_image = self.text_canvas(&frame, invert);
+        let area = self.text_area(&text_image);
+        let max_text_width = area.width() as u32;
+        let center = XY { x: (area.min.x + area.max.x) / 2, y: (area.min.y + area.max.y) / 2 };
+        // A line taller than the text area's longest side can't be placed on it, so larger sizes aren't tried
+        let size_limit = area.width().max(area.height()) as u32;
+        // Lines are centered by their ink, which can start some way from where the text is laid out
+        let centered_x = |text: &ResizedText| center.x - text.rendered_size.x as i32 / 2 - text.left;
 
         match secondary_text {
             Some(secondary_text) => {
-                let primary = ResizedText::create(&font, text, length, 90.0 * font_scale);
-                let secondary = ResizedText::create(&font, secondary_text, length, 35.0 * font_scale);
+                let primary = ResizedText::create(&fonts, text, max_text_width, 90.0 * font_scale, size_limit)?;
+                let secondary = ResizedText::create(&fonts, secondary_text, max_text_width, 35.0 * font_scale, size_limit)?;
 
+                // Both lines are centered together
+                let top = center.y - (primary.rendered_size.y + secondary.rendered_size.y) as i32 / 2;
                 let primary_offset = XY {
-                    x: (length as i32 / 2) - (primary.rendered_size.x as i32 / 2),
-                    y: (width  as i32 / 2) - (primary.rendered_size.y as i32 / 2) - 25,
+                    x: centered_x(&primary),
+                    y: top,
                 };
                 let secondary_offset = XY {
-                    x: (length as i32 / 2) - (secondary.rendered_size.x as i32 / 2),
-                    y: (width  as i32 / 1) - (secondary.rendered_size.y as i32 / 2) - 20,
+                    x: centered_x(&secondary),
+                    y: top + primary.rendered_size.y as i32,
                 };
-                draw_glyphs(&mut image, &primary.glyphs, primary_offset, invert);
-                draw_glyphs(&mut image, &secondary.glyphs, secondary_offset, invert);
+                draw_glyphs(&mut text_image, &fonts, &primary.glyphs, primary_offset, invert);
+                draw_glyphs(&mut text_image, &fonts, &secondary.glyphs, secondary_offset, invert);
             },
             None => {
-                let primary = ResizedText::create(&font, text, length, 125.0 * font_scale);
+                let primary = ResizedText::create(&fonts, text, max_text_width, 125.0 * font_scale, size_limit)?;
 
                 let offset = XY {
-                    x: (length as i32 / 2) - (primary.rendered_size.x as i32 / 2) - 5,
-                    y: (width  as i32 / 2) - (primary.rendered_size.y as i32 / 2),
+                    x: centered_x(&primary),
+                    y: center.y - (primary.rendered_size.y as i32 / 2),
                 };
 
-                draw_glyphs(&mut image, &primary.glyphs, offset, invert);
+                draw_glyphs(&mut text_image, &fonts, &primary.glyphs, offset, invert);
             }
         }
 
-        if let Some(image_path) = &self.second_row_image {
-            let overlay = image::open(image_path).unwrap().to_luma();
+        self.finish(&frame, text_image)
+    }
+
+    /// Lays out a paragraph of text on the label's printable area, breaking lines at spaces and explicit newlines.
+    /// The font size is reduced from the style's maximum until the whole paragraph fits within the style's padding.
+    /// If it doesn't fit at the style's minimum font size, lines that don't fit are cut off with an ellipsis.
+    pub fn rasterize_text(&self, text: &str, style: &TextStyle) -> Result<Vec<RasterLine>> {
+        let fonts = self.load_fonts()?;
+        let frame = self.frame();
+        let mut text_image = self.text_canvas(&frame, style.invert);
+        let area = pad(self.text_area(&text_image), &style.padding)?;
+        let paragraph = layout::fit(&fonts, text, area.width() as f32, area.height() as f32, style)?;
+        draw_paragraph(&mut text_image, &fonts, &paragraph, area, style);
+        self.finish(&frame, text_image)
+    }
+
+    fn load_fonts(&self) -> Result<FontChain> {
+        let load = |source: &FontSource| match source {
+            FontSource::Path(path) => CachedFont::load(path),
+            FontSource::Loaded(font) => Ok(font.clone()),
+        };
+        let fallbacks = self.fallback_fonts.iter().map(load).collect::<Result<_>>()?;
+        Ok(FontChain::new(load(&self.font)?, fallbacks))
+    }
+
+    fn frame(&self) -> Frame {
+        let mut length = 750;
+        // The image covers the label's printable area across the tape
+        let width = self.label.dots_printable.0;
+        let mut secondary_top = width;
+        let mut secondary_width = 0;
+
+        if self.label.tape_size.1 == 0 {
+            // Continuous tape
+            if self.label.tape_size.0 == 12 && self.second_row_image.is_some() {
+                // 12mm labels have a second label below the primary that can actually be used. It starts
+                // past the edge of the primary label.
+                secondary_top += self.label.dots.0.saturating_sub(self.label.dots_printable.0) / 2;
+                secondary_width = 170;
+            }
+        }
+        else {
+            // Die cut labels
+            length = self.label.dots_printable.1;
+        }
+        Frame { length, width, secondary_top, secondary_width }
+    }
+
+    /// The canvas text is laid out on. Text is laid out upright, and the canvas is then turned onto the label as set
+    /// by the raster options' orientation.
+    fn text_canvas(&self, frame: &Frame, invert: bool) -> GrayImage {
+        let (text_length, text_width) = if self.raster_options.orientation.swaps_axes() {
+            (frame.width, frame.length)
+        }
+        else {
+            (frame.length, frame.width)
+        };
+        let background = if invert { Luma([0]) } else { Luma([255]) };
+        GrayImage::from_pixel(text_length, text_width, background)
+    }
+
+    /// The part of the text canvas that text can be drawn on
+    fn text_area(&self, canvas: &GrayImage) -> Rect<i32> {
+        let (text_length, text_width) = canvas.dimensions();
+        let (area_length, area_width) = if self.label.shape == LabelShape::Round {
+            // Text on round labels has to fit within the square inscribed in the circle
+            let inscribed = (self.label.dots_printable.0 as f32 * std::f32::consts::FRAC_1_SQRT_2) as u32;
+            (inscribed, inscribed)
+        }
+        else {
+            (text_length, text_width)
+        };
+        // Signed so that an area larger than the canvas is centered over it instead of wrapping around
+        let min = XY {
+            x: (text_length as i32 - area_length as i32) / 2,
+            y: (text_width as i32 - area_width as i32) / 2,
+        };
+        Rect { min, max: XY { x: min.x + area_length as i32, y: min.y + area_width as i32 } }
+    }
+
+    /// Turns the text canvas onto the label, adds the second row image, and converts the result to raster lines
+    fn finish(&self, frame: &Frame, text_image: GrayImage) -> Result<Vec<RasterLine>> {
+        let mut text_image = orient(text_image, &self.raster_options);
+        if self.label.shape == LabelShape::Round {
+            clip_to_ellipse(&mut text_image);
+        }
+        let length = frame.length;
+        let secondary_width = frame.secondary_width;
+        let mut image = GrayImage::from_pixel(length, frame.secondary_top + secondary_width, Luma([255]));
+        image::imageops::overlay(&mut image, &text_image, 0, 0);
+
+        if let (Some(image_path), true) = (&self.second_row_image, secondary_width > 0) {
+            let overlay = image::open(image_path)?.to_luma();
 
             let top_margin = 15;
             let ratio = overlay.width() as f32 / overlay.height() as f32;
@@ -222,13 +450,168 @@ impl TextRasterizer {
                 new_width = (new_height as f32 * ratio) as u32;
             }
             let resized = image::imageops::resize(&overlay, new_width, new_height, image::FilterType::Triangle);
-            image::imageops::overlay(&mut image, &resized, (length - new_width) / 2, width);
+            image::imageops::overlay(&mut image, &resized, (length - new_width) / 2, frame.secondary_top);
         }
 
-        // Save the image to a png file if debug mode is enabled
-        if cfg!(debug_assertions) {
-            image.save("render.png").unwrap();
+        // Save the image for debugging if a path is set in the environment
+        if let Some(path) = std::env::var_os(RENDER_PATH_VARIABLE) {
+            // Only a debugging aid, so failing to write it isn't an error
+            let _ = image.save(path);
         }
-        image_to_raster_lines(&image, length)
+        image_to_raster_lines(image, self.print_head, self.label.head_offset(self.print_head), &self.raster_options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants::{ label_by_identifier, label_data };
+    use style::Length;
+
+    /// Tests that render text use this font and are ignored by default, since it isn't installed everywhere
+    pub(crate) const TEST_FONT_PATH: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+    /// The test font, parsed without the contents of its file so that text in it isn't shaped
+    pub(crate) fn test_font() -> Font<'static> {
+        let data = std::fs::read(TEST_FONT_PATH).expect("DejaVu Sans is not installed");
+        Font::from_bytes(data).expect("Invalid test font")
+    }
+
+    /// A chain of just the test font
+    pub(crate) fn test_font_chain() -> FontChain {
+        FontChain::new(Arc::new(CachedFont::new(Arc::new(test_font()))), Vec::new())
+    }
+
+    /// The inked pixels of raster lines rendered for `label` on a standard print head, relative to its printable area
+    fn ink(label: &Label, lines: &[RasterLine]) -> Vec<(u32, u32)> {
+        let offset = label.head_offset(PrintHead::Standard);
+        lines.iter().enumerate()
+            .flat_map(|(x, line)| (0..line.len() as u32 * 8)
+                .filter(move |pin| line[*pin as usize / 8] & (0x80 >> (pin % 8)) != 0)
+                .map(move |pin| (x as u32, pin - offset)))
+            .collect()
+    }
+
+    #[test]
+    fn round_text_area() {
+        let round = label_by_identifier("d24").unwrap().label;
+        let rasterizer = TextRasterizer::new(round, PathBuf::from(TEST_FONT_PATH));
+        let area = rasterizer.text_area(&rasterizer.text_canvas(&rasterizer.frame(), false));
+        // The 166 dot square inscribed in the 236 dot circle, centered on the label
+        assert_eq!((area.min.x, area.min.y, area.max.x, area.max.y), (35, 35, 201, 201));
+    }
+
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn text_alignment() {
+        let label = label_data(62, Some(100)).unwrap();
+        let rasterizer = TextRasterizer::new(label, PathBuf::from(TEST_FONT_PATH));
+        // The first and last raster lines with any printed dots, i.e. where the text starts and ends along the tape
+        let extent = |style: &TextStyle| {
+            let lines = rasterizer.rasterize_text("Price", style).unwrap();
+            let printed: Vec<usize> = lines.iter().enumerate().filter(|(_, line)| line.iter().any(|&byte| byte != 0)).map(|(index, _)| index).collect();
+            (printed[0], *printed.last().unwrap())
+        };
+        let style = TextStyle { max_font_size: 60.0, ..TextStyle::default() };
+        let (left, _) = extent(&TextStyle { horizontal_alignment: HorizontalAlignment::Left, ..style });
+        let (_, right) = extent(&TextStyle { horizontal_alignment: HorizontalAlignment::Right, ..style });
+        let (center_start, center_end) = extent(&style);
+        assert!(left < 10);
+        assert!(right > 1100);
+        assert!(center_start > 400 && center_end < 700);
+
+        let padding = Padding { left: Length::Millimeters(10.0), ..Padding::default() };
+        let (padded, _) = extent(&TextStyle { horizontal_alignment: HorizontalAlignment::Left, padding, ..style });
+        assert!((118..128).contains(&padded));
+        let style = TextStyle { vertical_alignment: VerticalAlignment::Top, padding: Padding::uniform(Length::Dots(600)), ..style };
+        assert!(matches!(rasterizer.rasterize_text("Price", &style), Err(Error::InvalidArgument(_))));
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    fn justified_word_order() {
+        assert_eq!(visual_words("Tel Aviv"), ["Tel", "Aviv"]);
+        assert_eq!(visual_words("שלום עולם טוב"), ["טוב", "עולם", "שלום"]);
+        // Left-to-right runs keep their order within right-to-left text and vice versa
+        assert_eq!(visual_words("Tel Aviv שלום עולם"), ["Tel", "Aviv", "עולם", "שלום"]);
+        assert_eq!(visual_words("שלום Tel Aviv עולם"), ["עולם", "Tel", "Aviv", "שלום"]);
+        assert_eq!(visual_words("a  b"), ["a", "", "b"]);
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn justify_right_to_left() {
+        let fonts = FontChain::new(CachedFont::load(std::path::Path::new(TEST_FONT_PATH)).unwrap(), Vec::new());
+        let scale = Scale::uniform(40.0);
+        let v_metrics = fonts.v_metrics("שלום", scale);
+        let paragraph = Paragraph {
+            lines: vec![layout::Line { text: "שלום אב".to_string(), ends_paragraph: false }],
+            scale,
+            ascent: v_metrics.ascent,
+            descent: v_metrics.descent,
+            line_height: v_metrics.ascent - v_metrics.descent,
+        };
+        let mut image = GrayImage::from_pixel(400, 60, Luma([255]));
+        let area = Rect { min: XY { x: 0, y: 0 }, max: XY { x: 400, y: 60 } };
+        let style = TextStyle { horizontal_alignment: HorizontalAlignment::Justify, ..TextStyle::default() };
+        draw_paragraph(&mut image, &fonts, &paragraph, area, &style);
+
+        // The line spans the whole area, with the first word read (the wider one) on the right
+        let inked: Vec<u32> = (0..400).filter(|&x| (0..60).any(|y| image.get_pixel(x, y)[0] < 128)).collect();
+        let gap = inked.windows(2).position(|pair| pair[1] - pair[0] > 50).unwrap();
+        let left = inked[gap] - inked[0];
+        let right = inked[inked.len() - 1] - inked[gap + 1];
+        assert!(inked[0] < 10 && inked[inked.len() - 1] > 390);
+        assert!(left < right, "{} dots wide on the left, {} on the right", left, right);
+    }
+
+    #[test]
+    fn missing_font() {
+        let label = label_by_identifier("62").unwrap().label;
+        let rasterizer = TextRasterizer::new(label, PathBuf::from("/nonexistent/font.ttf"));
+        assert!(rasterizer.rasterize("Text", None, 1.0, false).is_err());
+        assert!(rasterizer.rasterize_text("Text", &TextStyle::default()).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn text_edge_cases() {
+        let label = label_by_identifier("29x90").unwrap().label;
+        let rasterizer = TextRasterizer::new(label, PathBuf::from(TEST_FONT_PATH));
+        // Text without any ink has no bounding box
+        let blank = rasterizer.rasterize("", None, 1.0, false).unwrap();
+        assert_eq!(blank.len(), label.dots_printable.1 as usize);
+        assert!(blank.iter().flatten().all(|&byte| byte == 0));
+        assert!(rasterizer.rasterize(" ", Some(""), 1.0, false).unwrap().iter().flatten().all(|&byte| byte == 0));
+        assert!(rasterizer.rasterize(" Leading space", None, 1.0, false).unwrap().iter().flatten().any(|&byte| byte != 0));
+
+        // Shrinking stops at the smallest font size instead of looping forever
+        let long = "W".repeat(2000);
+        assert!(matches!(rasterizer.rasterize(&long, None, 1.0, false), Err(Error::Rasterization(_))));
+
+        // Huge scales start shrinking from the largest size that could fit on the label
+        let huge = rasterizer.rasterize("x", None, 1.0e6, false).unwrap();
+        assert_eq!(huge.len(), label.dots_printable.1 as usize);
+        assert!(huge.iter().flatten().any(|&byte| byte != 0));
+        for scale in &[0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(rasterizer.rasterize("x", None, *scale, false), Err(Error::InvalidArgument(_))));
+        }
+        let style = TextStyle { max_font_size: 1.0e8, ..TextStyle::default() };
+        assert!(rasterizer.rasterize_text("x", &style).unwrap().iter().flatten().any(|&byte| byte != 0));
+    }
+
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn round_text() {
+        let round = label_by_identifier("d24").unwrap().label;
+        let rasterizer = TextRasterizer::new(round, PathBuf::from(TEST_FONT_PATH));
+        let lines = rasterizer.rasterize_text("Text on a round label", &TextStyle::default()).unwrap();
+        let inked = ink(&round, &lines);
+        assert!(!inked.is_empty());
+        assert!(inked.iter().all(|&(x, y)| (35..201).contains(&x) && (35..201).contains(&y)));
+
+        let lines = rasterizer.rasterize("Round", None, 1.0, false).unwrap();
+        assert!(ink(&round, &lines).iter().all(|&(x, y)| (35..201).contains(&x) && (35..201).contains(&y)));
     }
 }
@@ -0,0 +1,404 @@
+//! Parsed fonts shared between rasterizers, and a cache of the glyphs rendered with them
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, RwLock, Weak };
+use std::time::SystemTime;
+use rusttype::{ Font, GlyphId, Point, PositionedGlyph, Scale, SharedBytes, VMetrics };
+use crate::error::{ Error, Result };
+#[cfg(feature = "shaping")]
+use super::shaping;
+
+/// Number of rendered glyphs kept for each font. The cache is emptied when it fills up.
+const GLYPH_CACHE_SIZE: usize = 4096;
+/// Glyphs are rendered at quarter pixel offsets, so that text keeps its spacing while most glyphs can be reused
+const SUBPIXEL_STEPS: f32 = 4.0;
+
+/// Fonts loaded from files, so that each file is only read and parsed again once it changes. Kept for the life of
+/// the process unless `clear_cache()` is called.
+static FONTS_BY_PATH: RwLock<Vec<LoadedFont>> = RwLock::new(Vec::new());
+/// Fonts parsed elsewhere that are still in use, so that every user of the same `Font` shares its rendered glyphs
+static SHARED_FONTS: Mutex<Vec<Weak<CachedFont>>> = Mutex::new(Vec::new());
+
+/// Forgets every font loaded from a file, so that files are read and parsed again the next time they're used
+pub(crate) fn clear_cache() {
+    FONTS_BY_PATH.write()
+        .unwrap_or_else(|err| err.into_inner())
+        .clear();
+}
+
+struct LoadedFont {
+    path: PathBuf,
+    /// Modification time of the file when it was read, if the platform has them
+    modified: Option<SystemTime>,
+    font: Arc<CachedFont>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct GlyphKey {
+    id: u32,
+    /// Bits of the horizontal and vertical scale
+    scale: (u32, u32),
+    /// Offset within the pixel, in subpixel steps
+    offset: (u8, u8),
+}
+
+/// A glyph's coverage of each pixel of its bounding box, from 0 (none) to 255 (fully covered)
+pub(crate) struct RenderedGlyph {
+    /// Top left corner of the bounding box, relative to the pixel the glyph is positioned in
+    pub min: Point<i32>,
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<u8>,
+}
+
+/// A parsed font along with the glyphs rendered from it so far
+pub(crate) struct CachedFont {
+    pub font: Arc<Font<'static>>,
+    /// The font file parsed for the shaper, which reads the font's OpenType layout tables from it. Fonts that were
+    /// parsed elsewhere don't have it and aren't shaped.
+    #[cfg(feature = "shaping")]
+    face: Option<shaping::ShapingFace>,
+    glyphs: Mutex<HashMap<GlyphKey, Arc<RenderedGlyph>>>,
+}
+impl CachedFont {
+    pub fn new(font: Arc<Font<'static>>) -> Self {
+        Self {
+            font,
+            #[cfg(feature = "shaping")]
+            face: None,
+            glyphs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wraps a font parsed elsewhere, sharing the glyphs rendered from it with everything else using the same `Font`
+    pub fn shared(font: Arc<Font<'static>>) -> Arc<Self> {
+        let mut shared = SHARED_FONTS.lock()
+            .unwrap_or_else(|err| err.into_inner());
+        shared.retain(|cached| cached.strong_count() > 0);
+        if let Some(cached) = shared.iter().filter_map(Weak::upgrade).find(|cached| Arc::ptr_eq(&cached.font, &font)) {
+            return cached;
+        }
+        let cached = Arc::new(Self::new(font));
+        shared.push(Arc::downgrade(&cached));
+        cached
+    }
+
+    /// Parses the contents of a TrueType or OpenType font file
+    pub fn from_data(data: SharedBytes<'static>) -> std::result::Result<Self, rusttype::Error> {
+        let font = Font::from_bytes(data.clone())?;
+        Ok(Self {
+            font: Arc::new(font),
+            #[cfg(feature = "shaping")]
+            face: shaping::ShapingFace::parse(data),
+            glyphs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Reads and parses a font file, or returns the font parsed the last time the file was loaded if it hasn't been
+    /// modified since
+    pub fn load(path: &Path) -> Result<Arc<Self>> {
+        let modified = fs::metadata(path)?.modified().ok();
+        let fonts = FONTS_BY_PATH.read()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some(loaded) = fonts.iter().find(|loaded| loaded.path == path && loaded.modified == modified) {
+            return Ok(loaded.font.clone());
+        }
+        drop(fonts);
+
+        let data = fs::read(path)?;
+        let font = Self::from_data(data.into())
+            .map_err(|err| Error::Rasterization(format!("Invalid font {}: {}", path.display(), err)))?;
+        let font = Arc::new(font);
+        let mut fonts = FONTS_BY_PATH.write()
+            .unwrap_or_else(|err| err.into_inner());
+        // The font parsed from the file's old contents is replaced
+        fonts.retain(|loaded| loaded.path != path);
+        fonts.push(LoadedFont { path: path.to_path_buf(), modified, font: font.clone() });
+        Ok(font)
+    }
+
+    /// Renders a glyph of this font, returning the pixel it is positioned in along with its coverage
+    pub fn render(&self, glyph: &PositionedGlyph) -> (Point<i32>, Arc<RenderedGlyph>) {
+        let position = glyph.position();
+        let pixel = Point { x: position.x.floor() as i32, y: position.y.floor() as i32 };
+        let offset = (
+            ((position.x - pixel.x as f32) * SUBPIXEL_STEPS).round() as u8,
+            ((position.y - pixel.y as f32) * SUBPIXEL_STEPS).round() as u8,
+        );
+        let scale = glyph.scale();
+        let key = GlyphKey {
+            id: glyph.id().0,
+            scale: (scale.x.to_bits(), scale.y.to_bits()),
+            offset,
+        };
+
+        let mut glyphs = self.glyphs.lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some(rendered) = glyphs.get(&key) {
+            return (pixel, rendered.clone());
+        }
+        let positioned = glyph.unpositioned().clone().positioned(Point {
+            x: offset.0 as f32 / SUBPIXEL_STEPS,
+            y: offset.1 as f32 / SUBPIXEL_STEPS,
+        });
+        let rendered = Arc::new(match positioned.pixel_bounding_box() {
+            Some(bounding_box) => {
+                let width = bounding_box.width() as u32;
+                let height = bounding_box.height() as u32;
+                let mut coverage = vec![0; (width * height) as usize];
+                positioned.draw(|x, y, v| {
+                    coverage[(y * width + x) as usize] = (v * 255.0).round() as u8;
+                });
+                RenderedGlyph { min: bounding_box.min, width, height, coverage }
+            },
+            // Glyphs without any pixels (e.g. spaces) don't have a bounding box
+            None => RenderedGlyph { min: Point { x: 0, y: 0 }, width: 0, height: 0, coverage: Vec::new() },
+        });
+        if glyphs.len() >= GLYPH_CACHE_SIZE {
+            glyphs.clear();
+        }
+        glyphs.insert(key, rendered.clone());
+        (pixel, rendered)
+    }
+}
+
+/// A glyph laid out by `FontChain::layout()`, along with the index of the font in the chain it comes from
+pub(crate) type ChainGlyph = (usize, PositionedGlyph<'static>);
+
+/// Fonts that are tried in order for each character, so that characters missing from the first font are drawn with
+/// the next font that has them
+pub(crate) struct FontChain {
+    /// Never empty. The first font is used for characters that none of the fonts have.
+    fonts: Vec<Arc<CachedFont>>,
+}
+impl FontChain {
+    pub fn new(primary: Arc<CachedFont>, fallbacks: Vec<Arc<CachedFont>>) -> Self {
+        let mut fonts = vec![primary];
+        fonts.extend(fallbacks);
+        Self { fonts }
+    }
+
+    /// Index of the first font with a glyph for `character`, if any of them has one
+    fn find_font(&self, character: char) -> Option<usize> {
+        self.fonts.iter().position(|font| font.font.glyph(character).id().0 != 0)
+    }
+
+    /// Index of the font `character` is drawn with
+    fn font_for(&self, character: char) -> usize {
+        self.find_font(character).unwrap_or(0)
+    }
+
+    /// Whether any of the fonts has a glyph for `character`
+    pub fn has_glyph(&self, character: char) -> bool {
+        self.find_font(character).is_some()
+    }
+
+    /// Lays out a line of text starting at `start`, returning each glyph along with the index of the font it comes
+    /// from. All glyphs share the same baseline, whichever font they come from.
+    pub fn layout(&self, text: &str, scale: Scale, start: Point<f32>) -> Vec<ChainGlyph> {
+        self.layout_line(text, scale, start).0
+    }
+
+    /// Distance the glyphs of a line of text advance
+    pub fn width(&self, text: &str, scale: Scale) -> f32 {
+        self.layout_line(text, scale, Point { x: 0.0, y: 0.0 }).1
+    }
+
+    #[cfg(not(feature = "shaping"))]
+    fn layout_line(&self, text: &str, scale: Scale, start: Point<f32>) -> (Vec<ChainGlyph>, f32) {
+        self.layout_unshaped(text, scale, start)
+    }
+
+    /// Lays out the text with each character's default glyph, one after the other from left to right
+    fn layout_unshaped(&self, text: &str, scale: Scale, start: Point<f32>) -> (Vec<ChainGlyph>, f32) {
+        let mut caret = 0.0;
+        let mut last: Option<(usize, GlyphId)> = None;
+        let glyphs = text.chars().map(|character| {
+            let index = self.font_for(character);
+            let font = &self.fonts[index].font;
+            let glyph = font.glyph(character).scaled(scale);
+            // Fonts only have kerning information for pairs of their own glyphs
+            if let Some((last_index, last_id)) = last {
+                if last_index == index {
+                    caret += font.pair_kerning(scale, last_id, glyph.id());
+                }
+            }
+            let advance = glyph.h_metrics().advance_width;
+            let glyph = glyph.positioned(Point { x: start.x + caret, y: start.y });
+            caret += advance;
+            last = Some((index, glyph.id()));
+            (index, glyph)
+        }).collect();
+        (glyphs, caret)
+    }
+
+    /// Lays out the text in display order, with each run of characters that come from the same font shaped by that
+    /// font's OpenType layout tables
+    #[cfg(feature = "shaping")]
+    fn layout_line(&self, text: &str, scale: Scale, start: Point<f32>) -> (Vec<ChainGlyph>, f32) {
+        let mut glyphs = Vec::new();
+        let mut caret = 0.0;
+        for (run, right_to_left) in shaping::visual_runs(text) {
+            let mut font_runs = self.font_runs(&text[run]);
+            if right_to_left {
+                font_runs.reverse();
+            }
+            for (index, run) in font_runs {
+                let origin = Point { x: start.x + caret, y: start.y };
+                let font = &self.fonts[index];
+                let shaped = font.face.as_ref()
+                    .map(|face| face.shape(&font.font, run, right_to_left, scale));
+                let advance = match shaped {
+                    Some((shaped, advance)) => {
+                        glyphs.extend(shaped.into_iter().map(|glyph| {
+                            let position = Point { x: origin.x + glyph.position.x, y: origin.y + glyph.position.y };
+                            (index, font.font.glyph(glyph.id).scaled(scale).positioned(position))
+                        }));
+                        advance
+                    },
+                    None => {
+                        // Without shaping, right-to-left text is at least drawn in the right order
+                        let run: String = if right_to_left { run.chars().rev().collect() } else { run.to_string() };
+                        let (unshaped, advance) = self.layout_unshaped(&run, scale, origin);
+                        glyphs.extend(unshaped);
+                        advance
+                    },
+                };
+                caret += advance;
+            }
+        }
+        (glyphs, caret)
+    }
+
+    /// Splits `text` into runs of characters that are drawn with the same font. Characters that none of the fonts
+    /// have stay with the run before them, so that marks aren't separated from the character they belong to.
+    #[cfg(feature = "shaping")]
+    fn font_runs<'t>(&self, text: &'t str) -> Vec<(usize, &'t str)> {
+        let mut runs: Vec<(usize, std::ops::Range<usize>)> = Vec::new();
+        for (offset, character) in text.char_indices() {
+            let end = offset + character.len_utf8();
+            match (runs.last_mut(), self.find_font(character)) {
+                (Some((last, range)), Some(index)) if *last == index => range.end = end,
+                (Some((_, range)), None) => range.end = end,
+                (_, index) => runs.push((index.unwrap_or(0), offset..end)),
+            }
+        }
+        runs.into_iter().map(|(index, range)| (index, &text[range])).collect()
+    }
+
+    /// Vertical metrics of `text`, tall enough for every font its characters are drawn with
+    pub fn v_metrics(&self, text: &str, scale: Scale) -> VMetrics {
+        let mut used = vec![false; self.fonts.len()];
+        used[0] = true;
+        for character in text.chars() {
+            used[self.font_for(character)] = true;
+        }
+        self.fonts.iter()
+            .zip(used)
+            .filter(|(_, used)| *used)
+            .map(|(font, _)| font.font.v_metrics(scale))
+            .fold(VMetrics { ascent: 0.0, descent: 0.0, line_gap: 0.0 }, |combined, metrics| VMetrics {
+                ascent: combined.ascent.max(metrics.ascent),
+                descent: combined.descent.min(metrics.descent),
+                line_gap: combined.line_gap.max(metrics.line_gap),
+            })
+    }
+
+    /// Renders a glyph laid out by `layout()`
+    pub fn render(&self, font: usize, glyph: &PositionedGlyph) -> (Point<i32>, Arc<RenderedGlyph>) {
+        self.fonts[font].render(glyph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::printer::constants::label_data;
+    use crate::text::TextRasterizer;
+    use crate::text::style::TextStyle;
+    use crate::text::layout::measure;
+    use crate::text::tests::{ test_font, TEST_FONT_PATH };
+
+    /// Has characters the test font lacks
+    const MATH_FONT_PATH: &str = "/usr/share/fonts/truetype/dejavu/DejaVuMathTeXGyre.ttf";
+
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn font_sources() {
+        let font = Arc::new(test_font());
+        let label = label_data(29, Some(90)).unwrap();
+        let from_path = TextRasterizer::new(label, PathBuf::from(TEST_FONT_PATH));
+        let from_data = TextRasterizer::from_font_data(label, fs::read(TEST_FONT_PATH).unwrap()).unwrap();
+        let from_font = TextRasterizer::from_font(label, font);
+        let expected = from_path.rasterize_text("Cached glyphs", &TextStyle::default()).unwrap();
+        // The second time, glyphs come from the cache
+        assert_eq!(from_path.rasterize_text("Cached glyphs", &TextStyle::default()).unwrap(), expected);
+        assert_eq!(from_data.rasterize_text("Cached glyphs", &TextStyle::default()).unwrap(), expected);
+        assert_eq!(from_font.rasterize_text("Cached glyphs", &TextStyle::default()).unwrap(), expected);
+
+        assert!(matches!(TextRasterizer::from_font_data(label, vec![0; 16]), Err(Error::Rasterization(_))));
+    }
+
+    #[test]
+    #[ignore = "needs DejaVu Sans and DejaVu Math TeX Gyre installed"]
+    fn font_fallback() {
+        let math_font_path = PathBuf::from(MATH_FONT_PATH);
+        let font = test_font();
+        let math_font = CachedFont::load(&math_font_path).expect("DejaVu Math TeX Gyre is not installed");
+        let scale = Scale::uniform(40.0);
+        let primary = Arc::new(CachedFont::new(Arc::new(font)));
+        let fonts = FontChain::new(primary.clone(), vec![math_font]);
+        // DejaVu Sans doesn't have U+210A SCRIPT SMALL G
+        assert!(primary.font.glyph('ℊ').id().0 == 0 && fonts.has_glyph('ℊ'));
+        let glyphs = fonts.layout("aℊa", scale, Point { x: 0.0, y: 0.0 });
+        assert_eq!(glyphs.iter().map(|(font, _)| *font).collect::<Vec<_>>(), [0, 1, 0]);
+        assert!(glyphs.iter().all(|(_, glyph)| glyph.position().y == 0.0));
+        // Lines are as tall as the tallest font used on them
+        let sans_metrics = primary.font.v_metrics(scale);
+        assert_eq!(fonts.v_metrics("a", scale).descent, sans_metrics.descent);
+        assert!(fonts.v_metrics("aℊa", scale).descent < sans_metrics.descent);
+        assert!(measure(&fonts, scale, "aℊa") > measure(&fonts, scale, "aa"));
+
+        let label = label_data(29, Some(90)).unwrap();
+        let mut rasterizer = TextRasterizer::from_font(label, primary.font.clone());
+        let missing = rasterizer.rasterize_text("ℊ", &TextStyle::default()).unwrap();
+        rasterizer.add_fallback_font_file(math_font_path);
+        assert_ne!(rasterizer.rasterize_text("ℊ", &TextStyle::default()).unwrap(), missing);
+        // Text the primary font has is unaffected
+        let expected = TextRasterizer::from_font(label, primary.font.clone()).rasterize_text("abc", &TextStyle::default()).unwrap();
+        assert_eq!(rasterizer.rasterize_text("abc", &TextStyle::default()).unwrap(), expected);
+    }
+
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn font_cache() {
+        // A copy of the test font that can be modified without affecting other tests
+        let path = std::env::temp_dir().join(format!("brother-ql-font-cache-{}.ttf", std::process::id()));
+        fs::copy(TEST_FONT_PATH, &path).unwrap();
+        let loaded = CachedFont::load(&path).unwrap();
+        assert!(Arc::ptr_eq(&CachedFont::load(&path).unwrap(), &loaded));
+
+        // A modified file is parsed again and replaces the old font
+        fs::File::options().write(true).open(&path).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        let reloaded = CachedFont::load(&path).unwrap();
+        assert!(!Arc::ptr_eq(&reloaded, &loaded));
+        assert!(Arc::ptr_eq(&CachedFont::load(&path).unwrap(), &reloaded));
+        let cached = |path: &Path| FONTS_BY_PATH.read().unwrap().iter().filter(|loaded| loaded.path == path).count();
+        assert_eq!(cached(&path), 1);
+
+        clear_cache();
+        assert_eq!(cached(&path), 0);
+        assert!(!Arc::ptr_eq(&CachedFont::load(&path).unwrap(), &reloaded));
+        fs::remove_file(&path).unwrap();
+
+        // Users of the same parsed font share its glyphs
+        let font = Arc::new(test_font());
+        let shared = CachedFont::shared(font.clone());
+        assert!(Arc::ptr_eq(&CachedFont::shared(font), &shared));
+        assert!(!Arc::ptr_eq(&CachedFont::shared(Arc::new(test_font())), &shared));
+    }
+}
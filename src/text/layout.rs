@@ -0,0 +1,266 @@
+//! Line breaking and sizing of paragraphs of text
+
+use rusttype::Scale;
+use crate::error::{ Error, Result };
+use super::font::FontChain;
+use super::style::TextStyle;
+
+const SOFT_HYPHEN: char = '\u{AD}';
+
+/// One line of a laid out paragraph
+pub(crate) struct Line {
+    pub text: String,
+    /// Whether the line ends at a newline or the end of the text rather than where the text was wrapped
+    pub ends_paragraph: bool,
+}
+
+/// A paragraph of text broken into lines at one font size
+pub(crate) struct Paragraph {
+    pub lines: Vec<Line>,
+    pub scale: Scale,
+    /// Distance from the top of a line to its baseline
+    pub ascent: f32,
+    /// Distance from the baseline to the bottom of a line, which is negative
+    pub descent: f32,
+    /// Distance between the baselines of consecutive lines
+    pub line_height: f32,
+}
+impl Paragraph {
+    /// Height of all lines, from the top of the first line to the bottom of the last
+    pub fn height(&self) -> f32 {
+        (self.lines.len().max(1) - 1) as f32 * self.line_height + self.ascent - self.descent
+    }
+}
+
+/// Width of a line of text, measured by how far the glyphs advance
+pub(crate) fn measure(fonts: &FontChain, scale: Scale, text: &str) -> f32 {
+    fonts.width(text, scale)
+}
+
+/// Text as it appears when a line isn't broken at its soft hyphens
+fn visible(text: &str) -> String {
+    text.replace(SOFT_HYPHEN, "")
+}
+
+/// Breaks `text` into lines at the largest font size from `style` at which the whole paragraph fits in `width` by
+/// `height` dots. If it doesn't fit at the minimum font size, lines are cut off with an ellipsis.
+pub(crate) fn fit(fonts: &FontChain, text: &str, width: f32, height: f32, style: &TextStyle) -> Result<Paragraph> {
+    if !style.min_font_size.is_finite() || !style.max_font_size.is_finite() || style.min_font_size < 1.0 || style.min_font_size > style.max_font_size {
+        return Err(Error::InvalidArgument("Font sizes must be at least 1 and the minimum can't exceed the maximum"));
+    }
+    if !style.line_spacing.is_finite() || style.line_spacing <= 0.0 {
+        return Err(Error::InvalidArgument("Line spacing must be greater than 0"));
+    }
+    let fitting = |font_size: f32| {
+        let paragraph = wrap(fonts, text, font_size, width, style);
+        let fits_width = paragraph.lines.iter().all(|line| measure(fonts, paragraph.scale, &line.text) <= width);
+        if fits_width && paragraph.height() <= height {
+            Ok(paragraph)
+        }
+        else {
+            Err(paragraph)
+        }
+    };
+    let smallest = match fitting(style.min_font_size) {
+        Ok(paragraph) => paragraph,
+        Err(paragraph) => return Ok(truncate(fonts, paragraph, width, height)),
+    };
+
+    // Sizes are tried 1 dot apart. A line is at least as tall as its font size, so sizes taller than the area are
+    // skipped.
+    let max_font_size = style.max_font_size.min(height).max(style.min_font_size);
+    let font_size = |step: u32| (max_font_size - step as f32).max(style.min_font_size);
+    // Find the first step from the maximum that fits by bisection, since smaller sizes fit whenever larger ones do
+    let (mut low, mut high) = (0, (max_font_size - style.min_font_size).ceil() as u32);
+    let mut paragraph = smallest;
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match fitting(font_size(middle)) {
+            Ok(fits) => {
+                paragraph = fits;
+                high = middle;
+            },
+            Err(_) => low = middle + 1,
+        }
+    }
+    Ok(paragraph)
+}
+
+/// Breaks `text` into lines no wider than `width` at word boundaries and explicit newlines. Single words that are
+/// wider than `width` are only broken if `style.hyphenate` is set.
+fn wrap(fonts: &FontChain, text: &str, font_size: f32, width: f32, style: &TextStyle) -> Paragraph {
+    let scale = Scale::uniform(font_size);
+    let fits = |line: &str| measure(fonts, scale, &visible(line)) <= width;
+
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            }
+            else {
+                format!("{} {}", line, word)
+            };
+            if fits(&candidate) {
+                line = candidate;
+                continue;
+            }
+
+            let mut word = word.to_string();
+            if style.hyphenate && !line.is_empty() {
+                // Fill up the current line with the start of the word if it can be broken nicely
+                if let Some((head, tail)) = hyphenate(&format!("{} ", line), &word, false, &fits) {
+                    lines.push((format!("{} {}", line, head), false));
+                    line.clear();
+                    word = tail;
+                }
+            }
+            if !line.is_empty() {
+                lines.push((std::mem::take(&mut line), false));
+            }
+            while style.hyphenate && !fits(&word) {
+                match hyphenate("", &word, true, &fits) {
+                    Some((head, tail)) => {
+                        lines.push((head, false));
+                        word = tail;
+                    },
+                    None => break,
+                }
+            }
+            line = word;
+        }
+        lines.push((line, true));
+    }
+    if lines.is_empty() {
+        lines.push((String::new(), true));
+    }
+
+    // All lines are spaced the same, even if only some of them use a taller fallback font
+    let v_metrics = fonts.v_metrics(&visible(text), scale);
+    Paragraph {
+        lines: lines.iter().map(|(text, ends_paragraph)| Line { text: visible(text), ends_paragraph: *ends_paragraph }).collect(),
+        scale,
+        ascent: v_metrics.ascent,
+        descent: v_metrics.descent,
+        line_height: (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap) * style.line_spacing,
+    }
+}
+
+/// Splits `word` at the last point where `prefix`, the start of the word and a hyphen still `fit`, returning the
+/// hyphenated start of the word and the rest. Words are split at soft hyphens and after hyphens, or at any other
+/// character if `anywhere` is set.
+fn hyphenate(prefix: &str, word: &str, anywhere: bool, fits: &dyn Fn(&str) -> bool) -> Option<(String, String)> {
+    let mut preferred = None;
+    let mut other = None;
+    let mut previous = None;
+    for (index, character) in word.char_indices() {
+        if index > 0 {
+            let split = if character == SOFT_HYPHEN {
+                Some((format!("{}-", &word[..index]), &word[index + SOFT_HYPHEN.len_utf8()..], true))
+            }
+            else if previous == Some('-') {
+                Some((word[..index].to_string(), &word[index..], true))
+            }
+            else if previous != Some(SOFT_HYPHEN) {
+                Some((format!("{}-", &word[..index]), &word[index..], false))
+            }
+            else {
+                None
+            };
+            if let Some((head, tail, nice)) = split {
+                if !visible(tail).is_empty() && fits(&format!("{}{}", prefix, head)) {
+                    if nice {
+                        preferred = Some((head, tail.to_string()));
+                    }
+                    else {
+                        other = Some((head, tail.to_string()));
+                    }
+                }
+            }
+        }
+        previous = Some(character);
+    }
+    preferred.or(if anywhere { other } else { None })
+}
+
+/// Drops the lines of `paragraph` that don't fit in `height` and shortens lines that are wider than `width`, marking
+/// where text was cut off with an ellipsis
+fn truncate(fonts: &FontChain, mut paragraph: Paragraph, width: f32, height: f32) -> Paragraph {
+    let text_height = paragraph.ascent - paragraph.descent;
+    // At least one line is kept even if it doesn't fit
+    let fitting_lines = ((height - text_height) / paragraph.line_height).floor().max(0.0) as usize + 1;
+    let cut_off = paragraph.lines.len() > fitting_lines;
+    paragraph.lines.truncate(fitting_lines);
+
+    // Fonts without an ellipsis character get three periods instead
+    let ellipsis = if fonts.has_glyph('…') { "…" } else { "..." };
+    let last = paragraph.lines.len() - 1;
+    for (index, line) in paragraph.lines.iter_mut().enumerate() {
+        if measure(fonts, paragraph.scale, &line.text) <= width && !(cut_off && index == last) {
+            continue;
+        }
+        let mut shortened = line.text.trim_end().to_string();
+        while !shortened.is_empty() && measure(fonts, paragraph.scale, &format!("{}{}", shortened, ellipsis)) > width {
+            shortened.pop();
+            shortened.truncate(shortened.trim_end().len());
+        }
+        line.text = format!("{}{}", shortened, ellipsis);
+        line.ends_paragraph = true;
+    }
+    paragraph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::tests::test_font_chain;
+
+    fn line_texts(paragraph: &Paragraph) -> Vec<&str> {
+        paragraph.lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    #[ignore = "needs DejaVu Sans installed"]
+    fn text_layout() {
+        let font = test_font_chain();
+        let style = TextStyle { max_font_size: 40.0, ..TextStyle::default() };
+        let paragraph = fit(&font, "The quick brown fox jumps over the lazy dog", 300.0, 1000.0, &style).unwrap();
+        assert_eq!(paragraph.scale.y, 40.0);
+        assert!(paragraph.lines.len() > 1);
+        assert!(paragraph.lines.iter().all(|line| measure(&font, paragraph.scale, &line.text) <= 300.0));
+        assert_eq!(line_texts(&paragraph).join(" "), "The quick brown fox jumps over the lazy dog");
+
+        let paragraph = fit(&font, "Name\n\nCompany", 300.0, 1000.0, &style).unwrap();
+        assert_eq!(line_texts(&paragraph), ["Name", "", "Company"]);
+
+        // Shrinks to fit the height
+        let paragraph = fit(&font, "The quick brown fox jumps over the lazy dog", 300.0, 100.0, &style).unwrap();
+        assert!(paragraph.scale.y < 40.0 && paragraph.height() <= 100.0);
+
+        // Cut off at the minimum font size
+        let style = TextStyle { max_font_size: 40.0, min_font_size: 30.0, ..TextStyle::default() };
+        let paragraph = fit(&font, "The quick brown fox jumps over the lazy dog", 300.0, 40.0, &style).unwrap();
+        assert_eq!(paragraph.lines.len(), 1);
+        assert!(paragraph.lines[0].text.starts_with("The quick") && paragraph.lines[0].text.ends_with('…'));
+
+        let style = TextStyle { max_font_size: 40.0, min_font_size: 40.0, hyphenate: true, ..TextStyle::default() };
+        let paragraph = fit(&font, "Donau\u{AD}dampf\u{AD}schiff\u{AD}fahrt", 250.0, 1000.0, &style).unwrap();
+        assert_eq!(line_texts(&paragraph), ["Donaudampf-", "schifffahrt"]);
+        let paragraph = fit(&font, "Donaudampfschifffahrt", 250.0, 1000.0, &style).unwrap();
+        assert!(paragraph.lines.len() > 1 && paragraph.lines[0].text.ends_with('-'));
+        assert!(paragraph.lines.iter().all(|line| measure(&font, paragraph.scale, &line.text) <= 250.0));
+
+        // Huge sizes are skipped instead of being tried one at a time
+        let style = TextStyle { max_font_size: 1.0e8, ..TextStyle::default() };
+        let paragraph = fit(&font, "x", 300.0, 100.0, &style).unwrap();
+        assert!(paragraph.scale.y > 90.0 && paragraph.height() <= 100.0);
+
+        let style = TextStyle { min_font_size: 0.0, ..TextStyle::default() };
+        assert!(matches!(fit(&font, "Text", 300.0, 100.0, &style), Err(Error::InvalidArgument(_))));
+        for size in &[f32::NAN, f32::INFINITY] {
+            let style = TextStyle { max_font_size: *size, ..TextStyle::default() };
+            assert!(matches!(fit(&font, "Text", 300.0, 100.0, &style), Err(Error::InvalidArgument(_))));
+        }
+    }
+}
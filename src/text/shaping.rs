@@ -0,0 +1,137 @@
+//! OpenType shaping and bidirectional reordering of lines of text, for scripts that can't be drawn one character at a
+//! time (e.g. Arabic or Devanagari) and for text that is read right to left
+
+use std::ops::Range;
+use rustybuzz::{ Direction, Face, UnicodeBuffer };
+use rusttype::{ Font, GlyphId, Point, Scale, SharedBytes };
+use self_cell::self_cell;
+use unicode_bidi::BidiInfo;
+
+/// A glyph picked and placed by the shaper
+pub(crate) struct ShapedGlyph {
+    pub id: GlyphId,
+    /// Position of the glyph's origin relative to the start of the text it was shaped from
+    pub position: Point<f32>,
+}
+
+/// Splits a line of text into runs that are read in the same direction, in the order they are displayed from left
+/// to right, along with whether each run is read right to left
+pub(crate) fn visual_runs(text: &str) -> Vec<(Range<usize>, bool)> {
+    let bidi = BidiInfo::new(text, None);
+    let mut runs = Vec::new();
+    for paragraph in &bidi.paragraphs {
+        let (levels, line_runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+        runs.extend(line_runs.into_iter().map(|run| {
+            let right_to_left = levels[run.start].is_rtl();
+            (run, right_to_left)
+        }));
+    }
+    runs
+}
+
+self_cell!(
+    /// The contents of a font file along with the shaper's view of its tables, so that the file is only parsed once
+    pub(crate) struct ShapingFace {
+        owner: SharedBytes<'static>,
+        #[covariant]
+        dependent: Face,
+    }
+);
+
+impl ShapingFace {
+    /// Parses the contents of a font file. Returns `None` if the shaper can't read the font.
+    pub fn parse(data: SharedBytes<'static>) -> Option<Self> {
+        Self::try_new(data, |data| Face::from_slice(data, 0).ok_or(())).ok()
+    }
+
+    /// Shapes `text` with `font`, which was parsed from the same file, returning the glyphs from left to right
+    /// along with the distance they advance
+    pub fn shape(&self, font: &Font, text: &str, right_to_left: bool, scale: Scale) -> (Vec<ShapedGlyph>, f32) {
+        shape(self.borrow_dependent(), font, text, right_to_left, scale)
+    }
+}
+
+fn shape(face: &Face, font: &Font, text: &str, right_to_left: bool, scale: Scale) -> (Vec<ShapedGlyph>, f32) {
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.set_direction(if right_to_left { Direction::RightToLeft } else { Direction::LeftToRight });
+    buffer.guess_segment_properties();
+    let shaped = rustybuzz::shape(face, &[], buffer);
+
+    // rusttype sizes fonts by the distance from their ascent to their descent rather than by their em square
+    let v_metrics = font.v_metrics_unscaled();
+    let units = v_metrics.ascent - v_metrics.descent;
+    let scale = Scale { x: scale.x / units, y: scale.y / units };
+    let mut caret = 0.0;
+    let glyphs = shaped.glyph_infos().iter().zip(shaped.glyph_positions()).map(|(info, position)| {
+        let glyph = ShapedGlyph {
+            id: GlyphId(info.glyph_id),
+            // Offsets in the font point up, while the text canvas's y axis points down
+            position: Point {
+                x: caret + position.x_offset as f32 * scale.x,
+                y: -position.y_offset as f32 * scale.y,
+            },
+        };
+        caret += position.x_advance as f32 * scale.x;
+        glyph
+    }).collect();
+    (glyphs, caret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::printer::constants::label_data;
+    use crate::text::TextRasterizer;
+    use crate::text::font::{ CachedFont, FontChain };
+    use crate::text::layout;
+    use crate::text::style::TextStyle;
+    use crate::text::tests::{ test_font, TEST_FONT_PATH };
+
+    #[test]
+    #[ignore = "needs DejaVu Sans and DejaVu Math TeX Gyre installed"]
+    fn text_shaping() {
+        let font = test_font();
+        let default_ids = |text: &str| -> Vec<u32> { text.chars().map(|character| font.glyph(character).id().0).collect() };
+        let data = fs::read(TEST_FONT_PATH).unwrap();
+        assert!(ShapingFace::parse(data.clone().into()).is_some());
+        let fonts = FontChain::new(Arc::new(CachedFont::from_data(data.into()).unwrap()), Vec::new());
+        let scale = Scale::uniform(40.0);
+        let ids = |fonts: &FontChain, text: &str| -> Vec<u32> {
+            fonts.layout(text, scale, Point { x: 0.0, y: 0.0 }).iter().map(|(_, glyph)| glyph.id().0).collect()
+        };
+
+        // Right-to-left text is reordered for display, and left-to-right runs within it keep their order
+        let runs: Vec<_> = visual_runs("Tel Aviv שלום").into_iter().map(|(run, rtl)| (run.start, rtl)).collect();
+        assert_eq!(runs, [(0, false), (9, true)]);
+        assert_eq!(ids(&fonts, "שלום"), default_ids("םולש"));
+        // Arabic letters take different forms depending on their neighbours
+        let arabic = ids(&fonts, "سلام");
+        assert_ne!(arabic, default_ids("مالس"));
+        // Kerning comes from the font's layout tables
+        assert!(layout::measure(&fonts, scale, "AV") < layout::measure(&fonts, scale, "A") + layout::measure(&fonts, scale, "V"));
+        // Ligatures, including the required lam-alef ligature of Arabic
+        assert_eq!(ids(&fonts, "fi").len(), 1);
+        assert_eq!(arabic.len(), 3);
+
+        // Shaped text is sized to fit like any other
+        let style = TextStyle { max_font_size: 60.0, ..TextStyle::default() };
+        let text = "مرحبا بالعالم، هذا نص طويل على ملصق";
+        let paragraph = layout::fit(&fonts, text, 300.0, 200.0, &style).unwrap();
+        assert!(paragraph.lines.len() > 1 && paragraph.height() <= 200.0);
+        assert!(paragraph.lines.iter().all(|line| layout::measure(&fonts, paragraph.scale, &line.text) <= 300.0));
+        let label = label_data(62, Some(29)).unwrap();
+        let rasterizer = TextRasterizer::new(label, PathBuf::from(TEST_FONT_PATH));
+        assert!(rasterizer.rasterize_text(text, &style).unwrap().iter().any(|line| line.iter().any(|&byte| byte != 0)));
+        // A fallback font given as data is shaped too, unlike one that was already parsed by rusttype
+        let math_font_path = PathBuf::from("/usr/share/fonts/truetype/dejavu/DejaVuMathTeXGyre.ttf");
+        let mut shaped_fallback = TextRasterizer::new(label, math_font_path.clone());
+        shaped_fallback.add_fallback_font_data(fs::read(TEST_FONT_PATH).unwrap()).unwrap();
+        let mut unshaped_fallback = TextRasterizer::new(label, math_font_path);
+        unshaped_fallback.add_fallback_font(Arc::new(test_font()));
+        assert_ne!(shaped_fallback.rasterize_text("سلام", &style).unwrap(), unshaped_fallback.rasterize_text("سلام", &style).unwrap());
+    }
+}
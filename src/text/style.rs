@@ -0,0 +1,108 @@
+//! Settings that control how a paragraph of text is laid out on a label
+
+use crate::printer::geometry::{ self, Axis, Resolution };
+
+/// Placement of lines of text between the left and right edges of the text area
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum HorizontalAlignment {
+    Left,
+    Center,
+    Right,
+    /// Lines are stretched to the full width by widening the spaces between words. The last line of each paragraph
+    /// is aligned left.
+    Justify,
+}
+
+/// Placement of the paragraph between the top and bottom edges of the text area
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum VerticalAlignment {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// A distance on the label
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Length {
+    Dots(u32),
+    Millimeters(f32),
+}
+impl Length {
+    /// The distance in dots. Text is always rendered at 300 dpi.
+    pub fn dots(self) -> u32 {
+        match self {
+            Length::Dots(dots) => dots,
+            Length::Millimeters(mm) => geometry::mm_to_dots(mm, Axis::Width, Resolution::Standard),
+        }
+    }
+}
+
+/// Space kept free between the edges of the label's printable area and the text. The sides are relative to the text,
+/// so lines start on the left.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Padding {
+    pub top: Length,
+    pub right: Length,
+    pub bottom: Length,
+    pub left: Length,
+}
+impl Padding {
+    /// The same padding on all sides
+    pub fn uniform(length: Length) -> Self {
+        Self {
+            top: length,
+            right: length,
+            bottom: length,
+            left: length,
+        }
+    }
+}
+impl Default for Padding {
+    fn default() -> Self {
+        Self::uniform(Length::Dots(0))
+    }
+}
+
+/// How `TextRasterizer::rasterize_text()` sizes, breaks and places a paragraph of text
+///
+/// Font sizes are in dots, i.e. the height of a line of text on the label. At 300 dpi, 12 dots are about 1mm.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct TextStyle {
+    /// The font size text is drawn at if it fits
+    pub max_font_size: f32,
+    /// The smallest font size text is shrunk to. Text that still doesn't fit is cut off with an ellipsis.
+    pub min_font_size: f32,
+    /// Distance between lines as a multiple of the font's line height
+    pub line_spacing: f32,
+    /// Break words that don't fit on a line with a hyphen. Words are broken at soft hyphens (U+00AD) and hyphens
+    /// where possible, and at any character otherwise.
+    pub hyphenate: bool,
+    /// Lines are centered by default
+    pub horizontal_alignment: HorizontalAlignment,
+    /// The paragraph is centered between the top and bottom by default
+    pub vertical_alignment: VerticalAlignment,
+    /// Space left free around the text, none by default
+    pub padding: Padding,
+    /// Print white text on a black background
+    pub invert: bool,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            max_font_size: 125.0,
+            min_font_size: 16.0,
+            line_spacing: 1.0,
+            hyphenate: false,
+            horizontal_alignment: HorizontalAlignment::Center,
+            vertical_alignment: VerticalAlignment::Middle,
+            padding: Padding::default(),
+            invert: false,
+        }
+    }
+}